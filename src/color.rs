@@ -0,0 +1,328 @@
+// Colorimetry used by the Processor. All of it is done in f64 as the
+// temperature search is sensitive to rounding.
+
+/// A 3x3 color matrix, in rows, that multiplies column vectors
+pub type Matrix3 = [[f64; 3]; 3];
+
+pub(crate) fn multiply(m: &Matrix3, v: [f64; 3]) -> [f64; 3] {
+	[
+		m[0][0] * v[0] + m[0][1] * v[1] + m[0][2] * v[2],
+		m[1][0] * v[0] + m[1][1] * v[1] + m[1][2] * v[2],
+		m[2][0] * v[0] + m[2][1] * v[1] + m[2][2] * v[2]
+	]
+}
+
+// a then b, as the one matrix b·a
+pub(crate) fn compose(b: &Matrix3, a: &Matrix3) -> Matrix3 {
+	let mut matrix = [[0.0; 3]; 3];
+	for (row, out) in matrix.iter_mut().enumerate() {
+		for (col, value) in out.iter_mut().enumerate() {
+			*value = (0..3).map(|k| b[row][k] * a[k][col]).sum();
+		}
+	}
+
+	matrix
+}
+
+// None if the matrix is singular
+pub(crate) fn invert(m: &Matrix3) -> Option<Matrix3> {
+	let cofactor = |r0: usize, r1: usize, c0: usize, c1: usize| {
+		m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0]
+	};
+
+	let det = m[0][0] * cofactor(1, 2, 1, 2)
+		- m[0][1] * cofactor(1, 2, 0, 2)
+		+ m[0][2] * cofactor(1, 2, 0, 1);
+
+	if det.abs() < 1e-12 {
+		return None;
+	}
+
+	Some([
+		[cofactor(1, 2, 1, 2) / det, -cofactor(0, 2, 1, 2) / det, cofactor(0, 1, 1, 2) / det],
+		[-cofactor(1, 2, 0, 2) / det, cofactor(0, 2, 0, 2) / det, -cofactor(0, 1, 0, 2) / det],
+		[cofactor(1, 2, 0, 1) / det, -cofactor(0, 2, 0, 1) / det, cofactor(0, 1, 0, 1) / det]
+	])
+}
+
+/// Temperatures we can work with, in kelvin. It's the range the Planckian
+/// locus approximation is good for.
+pub(crate) const TEMPERATURE_RANGE: (f64, f64) = (1000.0, 15000.0);
+
+// Tint is the distance from the Planckian locus in CIE 1960 uv, scaled like
+// Adobe's so useful values are about -150 to 150. Positive is magenta.
+const TINT_SCALE: f64 = 3000.0;
+
+// Krystek's rational approximation of the Planckian locus in CIE 1960 uv
+fn planckian_uv(kelvin: f64) -> (f64, f64) {
+	let t = kelvin;
+	let u = (0.860117757 + 1.54118254e-4 * t + 1.28641212e-7 * t * t)
+		/ (1.0 + 8.42420235e-4 * t + 7.08145163e-7 * t * t);
+	let v = (0.317398726 + 4.22806245e-5 * t + 4.20481691e-8 * t * t)
+		/ (1.0 - 2.89741816e-5 * t + 1.61456053e-7 * t * t);
+
+	(u, v)
+}
+
+// Unit vectors along the locus, towards higher temperatures, and across it
+// towards magenta
+fn locus_directions(kelvin: f64) -> ((f64, f64), (f64, f64)) {
+	let (u0, v0) = planckian_uv(kelvin - 1.0);
+	let (u1, v1) = planckian_uv(kelvin + 1.0);
+	let (du, dv) = (u1 - u0, v1 - v0);
+	let len = du.hypot(dv);
+	let tangent = (du / len, dv / len);
+
+	// Green is above the locus and magenta below it
+	let normal = if tangent.0 > 0.0 {
+		(tangent.1, -tangent.0)
+	} else {
+		(-tangent.1, tangent.0)
+	};
+
+	(tangent, normal)
+}
+
+/// The CIE 1931 xy chromaticity of a correlated color temperature and tint
+pub(crate) fn temperature_to_xy(kelvin: f64, tint: f64) -> (f64, f64) {
+	let kelvin = kelvin.max(TEMPERATURE_RANGE.0).min(TEMPERATURE_RANGE.1);
+	let (u, v) = planckian_uv(kelvin);
+	let (_, normal) = locus_directions(kelvin);
+
+	let offset = tint / TINT_SCALE;
+	uv_to_xy(u + normal.0 * offset, v + normal.1 * offset)
+}
+
+/// The correlated color temperature and tint of a CIE 1931 xy chromaticity.
+/// The temperature is where the locus is closest to it, clamped to the range
+/// we can work with.
+pub(crate) fn xy_to_temperature(x: f64, y: f64) -> (f64, f64) {
+	let (u, v) = xy_to_uv(x, y);
+
+	// How far along the locus the point is from the given temperature. It's
+	// zero where the point is closest to the locus.
+	let along = |kelvin: f64| {
+		let (lu, lv) = planckian_uv(kelvin);
+		let (tangent, _) = locus_directions(kelvin);
+		(u - lu) * tangent.0 + (v - lv) * tangent.1
+	};
+
+	// Bisect in mireds, which are far more even along the locus than kelvin
+	let (mut low, mut high) = (1e6 / TEMPERATURE_RANGE.1, 1e6 / TEMPERATURE_RANGE.0);
+	let kelvin = if along(1e6 / low) > 0.0 {
+		TEMPERATURE_RANGE.1
+	} else if along(1e6 / high) < 0.0 {
+		TEMPERATURE_RANGE.0
+	} else {
+		for _ in 0..64 {
+			let mid = (low + high) / 2.0;
+
+			// Along is positive when the point is further towards high
+			// temperatures, which are lower mireds
+			if along(1e6 / mid) > 0.0 {
+				high = mid;
+			} else {
+				low = mid;
+			}
+		}
+
+		1e6 / ((low + high) / 2.0)
+	};
+
+	let (lu, lv) = planckian_uv(kelvin);
+	let (_, normal) = locus_directions(kelvin);
+	let tint = ((u - lu) * normal.0 + (v - lv) * normal.1) * TINT_SCALE;
+
+	(kelvin, tint)
+}
+
+fn uv_to_xy(u: f64, v: f64) -> (f64, f64) {
+	let d = 2.0 * u - 8.0 * v + 4.0;
+	(3.0 * u / d, 2.0 * v / d)
+}
+
+fn xy_to_uv(x: f64, y: f64) -> (f64, f64) {
+	let d = -2.0 * x + 12.0 * y + 3.0;
+	(4.0 * x / d, 6.0 * y / d)
+}
+
+/// XYZ, with Y of 1.0, of a CIE 1931 xy chromaticity
+pub(crate) fn xy_to_xyz(x: f64, y: f64) -> [f64; 3] {
+	[x / y, 1.0, (1.0 - x - y) / y]
+}
+
+// CIE 1976 L*a*b* of XYZ, relative to the XYZ of the white. L is 0 to 100.
+// http://www.brucelindbloom.com/index.html?Eqn_XYZ_to_Lab.html
+pub(crate) fn xyz_to_lab(xyz: [f64; 3], white: [f64; 3]) -> [f64; 3] {
+	let f = |t: f64| {
+		if t > LAB_EPSILON {
+			t.cbrt()
+		} else {
+			(LAB_KAPPA * t + 16.0) / 116.0
+		}
+	};
+
+	let (fx, fy, fz) = (f(xyz[0] / white[0]), f(xyz[1] / white[1]), f(xyz[2] / white[2]));
+	[116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
+
+// http://www.brucelindbloom.com/index.html?Eqn_Lab_to_XYZ.html
+pub(crate) fn lab_to_xyz(lab: [f64; 3], white: [f64; 3]) -> [f64; 3] {
+	let fy = (lab[0] + 16.0) / 116.0;
+	let fx = fy + lab[1] / 500.0;
+	let fz = fy - lab[2] / 200.0;
+
+	let f_inv = |f: f64| {
+		if f.powi(3) > LAB_EPSILON {
+			f.powi(3)
+		} else {
+			(116.0 * f - 16.0) / LAB_KAPPA
+		}
+	};
+
+	[f_inv(fx) * white[0], f_inv(fy) * white[1], f_inv(fz) * white[2]]
+}
+
+const LAB_EPSILON: f64 = 216.0 / 24389.0;
+const LAB_KAPPA: f64 = 24389.0 / 27.0;
+
+// Lightness, chroma, and hue in degrees from 0 up to 360, of Lab or Oklab
+pub(crate) fn lab_to_lch(lab: [f64; 3]) -> [f64; 3] {
+	let hue = lab[2].atan2(lab[1]).to_degrees();
+	[lab[0], lab[1].hypot(lab[2]), if hue < 0.0 { hue + 360.0 } else { hue }]
+}
+
+pub(crate) fn lch_to_lab(lch: [f64; 3]) -> [f64; 3] {
+	let hue = lch[2].to_radians();
+	[lch[0], lch[1] * hue.cos(), lch[1] * hue.sin()]
+}
+
+// Björn Ottosson's, from XYZ with a D65 white to the cone responses and from
+// the cube roots of those to Oklab
+// https://bottosson.github.io/posts/oklab/
+const OKLAB_M1: Matrix3 = [
+	[0.8189330101, 0.3618667424, -0.1288597137],
+	[0.0329845436, 0.9293118715, 0.0361456387],
+	[0.0482003018, 0.2643662691, 0.6338517070]
+];
+
+const OKLAB_M2: Matrix3 = [
+	[0.2104542553, 0.7936177850, -0.0040720468],
+	[1.9779984951, -2.4285922050, 0.4505937099],
+	[0.0259040371, 0.7827717662, -0.8086757660]
+];
+
+// The inverses of the two above, for going back
+const OKLAB_M1_INVERSE: Matrix3 = [
+	[1.2270138511035211, -0.5577999806518222, 0.28125614896646783],
+	[-0.04058017842328059, 1.11225686961683, -0.0716766786656012],
+	[-0.07638128450570689, -0.4214819784180127, 1.5861632204407947]
+];
+
+const OKLAB_M2_INVERSE: Matrix3 = [
+	[0.9999999984505198, 0.39633779217376786, 0.2158037580607588],
+	[1.0000000088817609, -0.10556134232365635, -0.06385417477170591],
+	[1.0000000546724108, -0.08948418209496575, -1.2914855378640917]
+];
+
+// Oklab of XYZ with a D65 white. L is 0 to 1.
+pub(crate) fn xyz_to_oklab(xyz: [f64; 3]) -> [f64; 3] {
+	multiply(&OKLAB_M2, multiply(&OKLAB_M1, xyz).map(f64::cbrt))
+}
+
+pub(crate) fn oklab_to_xyz(lab: [f64; 3]) -> [f64; 3] {
+	multiply(&OKLAB_M1_INVERSE, multiply(&OKLAB_M2_INVERSE, lab).map(|lms| lms.powi(3)))
+}
+
+#[cfg(test)]
+mod color_tests {
+	use super::*;
+	use crate::test_util::assert_close;
+
+	#[test]
+	fn planckian_xy() {
+		// CIE Illuminant A is a 2856K blackbody
+		assert_close(temperature_to_xy(2856.0, 0.0), (0.44757, 0.40745), 5e-4);
+		// Blackbody at 5000K and 6500K
+		assert_close(temperature_to_xy(5000.0, 0.0), (0.34510, 0.35162), 5e-4);
+		assert_close(temperature_to_xy(6500.0, 0.0), (0.31352, 0.32363), 5e-4);
+	}
+
+	#[test]
+	fn tint_direction() {
+		// Magenta has less green, which is lower y
+		let (_, neutral) = temperature_to_xy(5000.0, 0.0);
+		let (_, magenta) = temperature_to_xy(5000.0, 20.0);
+		let (_, green) = temperature_to_xy(5000.0, -20.0);
+
+		assert!(magenta < neutral && neutral < green);
+	}
+
+	#[test]
+	fn temperature_round_trip() {
+		for &kelvin in [2000.0, 2856.0, 4000.0, 5500.0, 6504.0, 9000.0, 12000.0].iter() {
+			for &tint in [-50.0, -10.0, 0.0, 10.0, 50.0].iter() {
+				let (x, y) = temperature_to_xy(kelvin, tint);
+				let (k, t) = xy_to_temperature(x, y);
+
+				assert!((k - kelvin).abs() / kelvin < 1e-4, "{}K became {}K", kelvin, k);
+				assert!((t - tint).abs() < 0.05, "tint {} became {}", tint, t);
+			}
+		}
+	}
+
+	#[test]
+	fn d65_temperature() {
+		// D65 is about 6504K, and a bit green of the locus
+		let (kelvin, tint) = xy_to_temperature(0.31271, 0.32902);
+		assert!((kelvin - 6504.0).abs() < 25.0, "D65 was {}K", kelvin);
+		assert!(tint < 0.0 && tint > -15.0, "D65 tint was {}", tint);
+	}
+
+	#[test]
+	fn lab() {
+		let d65 = xy_to_xyz(0.31271, 0.32902);
+
+		// sRGB red, green, and blue, as XYZ, to Lab and LCh
+		let red = xyz_to_lab([0.4124564, 0.2126729, 0.0193339], d65);
+		assert_close(red, [53.2408, 80.0925, 67.2032], 0.01);
+		assert_close(lab_to_lch(red), [53.2408, 104.5518, 39.9990], 0.01);
+		assert_close(xyz_to_lab([0.3575761, 0.7151522, 0.1191920], d65), [87.7347, -86.1827, 83.1793], 0.01);
+		assert_close(xyz_to_lab([0.1804375, 0.0721750, 0.9503041], d65), [32.2970, 79.1875, -107.8602], 0.01);
+
+		// White is white whatever it is
+		let d50 = xy_to_xyz(0.34567, 0.35850);
+		assert_close(xyz_to_lab(d50, d50), [100.0, 0.0, 0.0], 1e-9);
+
+		for &xyz in [[0.2, 0.3, 0.4], [0.001, 0.002, 0.0005], [0.9, 1.0, 1.1]].iter() {
+			assert_close(lab_to_xyz(xyz_to_lab(xyz, d50), d50), xyz, 1e-9);
+			assert_close(lch_to_lab(lab_to_lch(xyz_to_lab(xyz, d50))), xyz_to_lab(xyz, d50), 1e-9);
+		}
+	}
+
+	#[test]
+	fn oklab() {
+		// Björn Ottosson's table of XYZ and Oklab
+		assert_close(xyz_to_oklab([0.950, 1.000, 1.089]), [1.000, 0.000, 0.000], 1e-3);
+		assert_close(xyz_to_oklab([1.000, 0.000, 0.000]), [0.450, 1.236, -0.019], 1e-3);
+		assert_close(xyz_to_oklab([0.000, 1.000, 0.000]), [0.922, -0.671, 0.263], 1e-3);
+		assert_close(xyz_to_oklab([0.000, 0.000, 1.000]), [0.153, -1.415, -0.449], 1e-3);
+
+		for &xyz in [[0.2, 0.3, 0.4], [0.001, 0.002, 0.0005], [0.9, 1.0, 1.1]].iter() {
+			assert_close(oklab_to_xyz(xyz_to_oklab(xyz)), xyz, 1e-9);
+		}
+
+		assert_close(OKLAB_M1_INVERSE, invert(&OKLAB_M1).unwrap(), 1e-12);
+		assert_close(OKLAB_M2_INVERSE, invert(&OKLAB_M2).unwrap(), 1e-12);
+	}
+
+	#[test]
+	fn invert_matrix() {
+		let m = [[2.0, 0.0, 1.0], [1.0, 3.0, 0.0], [0.0, 1.0, 4.0]];
+		let inv = invert(&m).unwrap();
+		let v = multiply(&inv, multiply(&m, [0.3, -0.2, 0.7]));
+
+		assert!((v[0] - 0.3).abs() < 1e-12 && (v[1] + 0.2).abs() < 1e-12 && (v[2] - 0.7).abs() < 1e-12);
+		assert!(invert(&[[1.0, 2.0, 3.0], [2.0, 4.0, 6.0], [0.0, 1.0, 0.0]]).is_none());
+	}
+}
@@ -0,0 +1,247 @@
+//! Getting from camera colors to an output color space. The camera matrix
+//! takes white balanced camera RGB to XYZ, chromatic adaptation moves the
+//! white from the one the matrix was made for to the output's, and the output
+//! primaries take XYZ to RGB. Each step is a plain 3x3 matrix in a
+//! [ColorTransform] so it can be looked at or replaced.
+
+use crate::color;
+use crate::image::Metadata;
+use crate::Processor;
+
+pub use crate::color::Matrix3;
+
+/// A CIE 1931 xy chromaticity
+pub type Chromaticity = (f64, f64);
+
+/// The white of CIE standard illuminant D50, used by ICC profiles and
+/// ProPhoto RGB
+pub const D50: Chromaticity = (0.34567, 0.35850);
+/// The white of CIE standard illuminant D65, used by sRGB and most displays.
+/// Camera matrices are made for it.
+pub const D65: Chromaticity = (0.31271, 0.32902);
+
+/// An RGB color space's primaries and white, which is all its linear
+/// conversion to and from XYZ needs
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Primaries {
+	pub red: Chromaticity,
+	pub green: Chromaticity,
+	pub blue: Chromaticity,
+	pub white: Chromaticity
+}
+
+impl Primaries {
+	/// sRGB and Rec. 709
+	pub const SRGB: Primaries = Primaries {
+		red: (0.64, 0.33),
+		green: (0.30, 0.60),
+		blue: (0.15, 0.06),
+		white: D65
+	};
+
+	/// The matrix from linear RGB in these primaries to XYZ. White, RGB of
+	/// all 1.0, becomes the white point with a Y of 1.0.
+	pub fn to_xyz(&self) -> Matrix3 {
+		let columns = [self.red, self.green, self.blue].map(|(x, y)| color::xy_to_xyz(x, y));
+		let primaries = [
+			[columns[0][0], columns[1][0], columns[2][0]],
+			[columns[0][1], columns[1][1], columns[2][1]],
+			[columns[0][2], columns[1][2], columns[2][2]]
+		];
+
+		// Each primary is scaled so they add up to the white
+		let white = color::xy_to_xyz(self.white.0, self.white.1);
+		let scale = color::invert(&primaries)
+			.map(|inverse| color::multiply(&inverse, white))
+			.unwrap_or([0.0; 3]);
+
+		primaries.map(|row| [row[0] * scale[0], row[1] * scale[1], row[2] * scale[2]])
+	}
+
+	/// The matrix from XYZ to linear RGB in these primaries. None if they
+	/// don't make a color space, like when two of them are the same.
+	pub fn from_xyz(&self) -> Option<Matrix3> {
+		color::invert(&self.to_xyz())
+	}
+}
+
+/// How to move colors from one white to another. Each works in a different
+/// approximation of the cone responses, where the whites are scaled to match.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Adaptation {
+	/// Don't adapt. Colors keep their XYZ, so the source white won't be
+	/// white in the output.
+	None,
+	/// The Bradford transform. It's what ICC profiles use.
+	Bradford,
+	/// The transform from CIECAM02
+	Cat02
+}
+
+const BRADFORD: Matrix3 = [
+	[0.8951, 0.2664, -0.1614],
+	[-0.7502, 1.7135, 0.0367],
+	[0.0389, -0.0685, 1.0296]
+];
+
+const CAT02: Matrix3 = [
+	[0.7328, 0.4296, -0.1624],
+	[-0.7036, 1.6975, 0.0061],
+	[0.0030, 0.0136, 0.9834]
+];
+
+const IDENTITY: Matrix3 = [
+	[1.0, 0.0, 0.0],
+	[0.0, 1.0, 0.0],
+	[0.0, 0.0, 1.0]
+];
+
+impl Adaptation {
+	/// The matrix, in XYZ, that takes colors seen under the `from` white to
+	/// how they'd look under the `to` white
+	pub fn matrix(&self, from: Chromaticity, to: Chromaticity) -> Matrix3 {
+		let cone = match self {
+			Adaptation::None => return IDENTITY,
+			Adaptation::Bradford => BRADFORD,
+			Adaptation::Cat02 => CAT02
+		};
+
+		let source = color::multiply(&cone, color::xy_to_xyz(from.0, from.1));
+		let destination = color::multiply(&cone, color::xy_to_xyz(to.0, to.1));
+		let scale = [
+			[destination[0] / source[0], 0.0, 0.0],
+			[0.0, destination[1] / source[1], 0.0],
+			[0.0, 0.0, destination[2] / source[2]]
+		];
+
+		// The cone matrices are all invertible
+		let inverse = color::invert(&cone).unwrap_or(IDENTITY);
+		color::compose(&inverse, &color::compose(&scale, &cone))
+	}
+}
+
+/// The matrices that take white balanced camera RGB to an output color
+/// space. They're applied in order, camera to XYZ, then the adaptation, then
+/// XYZ to output, and can all be changed before it's used.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ColorTransform {
+	/// Camera RGB to XYZ. Camera white, where every color is equal after
+	/// white balance, goes to the source white.
+	pub camera_to_xyz: Matrix3,
+	/// Chromatic adaptation from the source white to the output's
+	pub adaptation: Matrix3,
+	/// XYZ to the output's linear RGB
+	pub xyz_to_output: Matrix3
+}
+
+impl ColorTransform {
+	/// The transform for the camera in the metadata to `output`, adapting
+	/// from D65, which camera matrices are made for, to the output's white.
+	/// None if there's no camera matrix in the metadata or the output
+	/// primaries can't be inverted.
+	pub fn new(meta: &Metadata, output: &Primaries, adaptation: Adaptation) -> Option<Self> {
+		Self::from_camera_to_xyz(camera_to_xyz(meta)?, D65, output, adaptation)
+	}
+
+	/// The transform with your own camera to XYZ matrix, which makes camera
+	/// white into `source_white`
+	pub fn from_camera_to_xyz(camera_to_xyz: Matrix3, source_white: Chromaticity, output: &Primaries, adaptation: Adaptation) -> Option<Self> {
+		Some(Self {
+			camera_to_xyz,
+			adaptation: adaptation.matrix(source_white, output.white),
+			xyz_to_output: output.from_xyz()?
+		})
+	}
+
+	/// All three matrices as one, from camera RGB to output RGB
+	pub fn matrix(&self) -> Matrix3 {
+		color::compose(&self.xyz_to_output, &color::compose(&self.adaptation, &self.camera_to_xyz))
+	}
+}
+
+/// The camera's matrix from white balanced camera RGB to XYZ, with camera
+/// white going to D65. It comes from libraw's XYZ to camera matrix, and from
+/// its camera to sRGB one if that's missing. None if there's neither.
+pub fn camera_to_xyz(meta: &Metadata) -> Option<Matrix3> {
+	if let Some(xyz_to_camera) = Processor::xyz_to_camera(meta) {
+		// Each row is scaled so D65 gives equal camera colors, like dcraw does
+		// to make white balanced white neutral
+		let white = color::xy_to_xyz(D65.0, D65.1);
+		let normalized = xyz_to_camera.map(|row| {
+			let sum = row[0] * white[0] + row[1] * white[1] + row[2] * white[2];
+			row.map(|value| value / sum)
+		});
+
+		return color::invert(&normalized);
+	}
+
+	let rgb_cam = meta.colordata.rgb_cam;
+	if rgb_cam.iter().all(|row| row[..3].iter().all(|&value| value == 0.0)) {
+		return None;
+	}
+
+	let camera_to_srgb = [0, 1, 2].map(|row| [0, 1, 2].map(|col| rgb_cam[row][col] as f64));
+	Some(color::compose(&Primaries::SRGB.to_xyz(), &camera_to_srgb))
+}
+
+#[cfg(test)]
+mod color_management_tests {
+	use super::*;
+	use crate::image::CFA;
+	use crate::test_util::assert_close;
+
+	#[test]
+	fn srgb_to_xyz() {
+		// From IEC 61966-2-1, by way of Bruce Lindbloom
+		assert_close(Primaries::SRGB.to_xyz(), [
+			[0.4124564, 0.3575761, 0.1804375],
+			[0.2126729, 0.7151522, 0.0721750],
+			[0.0193339, 0.1191920, 0.9503041]
+		], 2e-4);
+	}
+
+	#[test]
+	fn adaptation_matrices() {
+		// Bruce Lindbloom's D65 to D50
+		assert_close(Adaptation::Bradford.matrix(D65, D50), [
+			[1.0478112, 0.0228866, -0.0501270],
+			[0.0295424, 0.9904844, -0.0170491],
+			[-0.0092345, 0.0150436, 0.7521316]
+		], 2e-4);
+
+		// Whatever the method, the source white goes to the destination's
+		for &method in [Adaptation::Bradford, Adaptation::Cat02].iter() {
+			let white = color::multiply(&method.matrix(D65, D50), color::xy_to_xyz(D65.0, D65.1));
+			let expected = color::xy_to_xyz(D50.0, D50.1);
+			assert!(white.iter().zip(expected.iter()).all(|(a, b)| (a - b).abs() < 1e-9), "{:?}", method);
+		}
+
+		assert_close(Adaptation::Cat02.matrix(D65, D65), IDENTITY, 1e-12);
+	}
+
+	#[test]
+	fn camera_to_srgb() {
+		// A real camera's matrix, and the camera to sRGB matrix libraw makes
+		// from it
+		let meta = Metadata::test_camera(2, 2, CFA::RGGB);
+
+		let transform = ColorTransform::new(&meta, &Primaries::SRGB, Adaptation::Bradford).unwrap();
+		assert_close(transform.adaptation, IDENTITY, 1e-12);
+
+		// dcraw's way, camera from sRGB with rows normalized, then inverted
+		let srgb_to_camera = color::compose(&Processor::xyz_to_camera(&meta).unwrap(), &Primaries::SRGB.to_xyz())
+			.map(|row| {
+				let sum: f64 = row.iter().sum();
+				row.map(|value| value / sum)
+			});
+		assert_close(transform.matrix(), color::invert(&srgb_to_camera).unwrap(), 1e-3);
+
+		// Camera white stays white
+		let white = color::multiply(&transform.matrix(), [1.0, 1.0, 1.0]);
+		assert_close(white, [1.0; 3], 1e-3);
+
+		// Without any matrices there's nothing to go on
+		let meta = Metadata::test(2, 2, CFA::RGGB);
+		assert!(ColorTransform::new(&meta, &Primaries::SRGB, Adaptation::Bradford).is_none());
+	}
+}
@@ -0,0 +1,207 @@
+use super::{bayer_only, bayer_only_interpolate, expand, mirrored, Demosaic};
+use crate::color::{self, Matrix3};
+use crate::color_management::{self, Primaries, D65};
+use crate::image::{Color, Image, Metadata, Rgb, Sensor};
+
+// Adaptive Homogeneity-Directed, from Hirakawa and Parks' "Adaptive
+// homogeneity-directed demosaicing algorithm".
+//
+// The image is interpolated twice, once with green interpolated horizontally
+// and once vertically, and red and blue following from each green. Both are
+// put into CIELab and, for every pixel, whichever is more homogeneous around
+// it is kept. Interpolating along an edge gives neighbours that are close in
+// both lightness and color, so the direction that's more homogeneous is the
+// one that didn't cross an edge.
+
+// The direct neighbours, left and right then up and down
+const DIRECT: [(i32, i32); 4] = [(-1, 0), (1, 0), (0, -1), (0, 1)];
+const DIAGONAL: [(i32, i32); 4] = [(-1, -1), (1, -1), (-1, 1), (1, 1)];
+
+/// See [Interpolation::Ahd](super::Interpolation::Ahd)
+pub struct Ahd;
+
+impl Demosaic for Ahd {
+	fn demosaic(&self, sensor: Image<Sensor, f32>) -> Image<Rgb, f32> {
+		self.demosaic_expanded(expand(sensor))
+	}
+
+	fn demosaic_expanded(&self, img: Image<Rgb, f32>) -> Image<Rgb, f32> {
+		bayer_only_interpolate(img, Self::interpolate)
+	}
+
+	// Green reads two pixels out, red and blue one more, homogeneity one
+	// more, and it's summed over the 3x3
+	fn reach(&self, meta: &Metadata) -> Option<u32> {
+		bayer_only(meta, 5)
+	}
+}
+
+impl Ahd {
+	fn interpolate(img: &mut Image<Rgb, f32>) {
+		let horizontal = Self::directional(img, (1, 0));
+		let vertical = Self::directional(img, (0, 1));
+
+		// Without a camera matrix the data is taken to already be sRGB
+		let to_xyz = color_management::camera_to_xyz(&img.meta).unwrap_or_else(|| Primaries::SRGB.to_xyz());
+		let white = color::xy_to_xyz(D65.0, D65.1);
+		let horizontal_lab: Vec<[f32; 3]> = horizontal.iter().map(|&rgb| Self::lab(&to_xyz, white, rgb)).collect();
+		let vertical_lab: Vec<[f32; 3]> = vertical.iter().map(|&rgb| Self::lab(&to_xyz, white, rgb)).collect();
+
+		let (horizontal_homogeneity, vertical_homogeneity) =
+			Self::homogeneity(&img.meta, &horizontal_lab, &vertical_lab);
+
+		for pix in img.pixel_range() {
+			let (x, y) = img.meta.itoxy(pix);
+			let current = img.meta.color_at_xy(x, y);
+
+			// Homogeneity is summed over the 3x3 so single pixels don't flip
+			// direction on their own
+			let mut scores = (0, 0);
+			for dy in -1..=1 {
+				for dx in -1..=1 {
+					let (nx, ny) = mirrored(&img.meta, x, y, dx, dy);
+					let i = img.meta.xytoi(nx, ny);
+					scores.0 += horizontal_homogeneity[i];
+					scores.1 += vertical_homogeneity[i];
+				}
+			}
+
+			let i = img.meta.xytoi(x, y);
+			let rgb = if scores.0 > scores.1 {
+				horizontal[i]
+			} else if scores.1 > scores.0 {
+				vertical[i]
+			} else {
+				[
+					(horizontal[i][0] + vertical[i][0]) / 2.0,
+					(horizontal[i][1] + vertical[i][1]) / 2.0,
+					(horizontal[i][2] + vertical[i][2]) / 2.0
+				]
+			};
+
+			for &color in [Color::Red, Color::Green, Color::Blue].iter() {
+				if color != current {
+					img.set_component(pix, color, rgb[color as usize]);
+				}
+			}
+		}
+	}
+
+	// The whole image with green interpolated along `direction`, and red and
+	// blue from the color difference to that green
+	fn directional(img: &Image<Rgb, f32>, direction: (i32, i32)) -> Vec<[f32; 3]> {
+		let meta = &img.meta;
+		let raw = |x: u32, y: u32, dx: i32, dy: i32| {
+			let (nx, ny) = mirrored(meta, x, y, dx, dy);
+			img.component(nx, ny, meta.color_at_xy(nx, ny))
+		};
+
+		// Green is the mean of the two greens beside a photosite, corrected by
+		// the second derivative of the photosite's own color. It's kept between
+		// the two greens so it can't overshoot.
+		let (dx, dy) = direction;
+		let green: Vec<f32> = (0..meta.width * meta.height).map(|i| {
+			let (x, y) = meta.itoxy(i as usize);
+
+			if meta.color_at_xy(x, y) == Color::Green {
+				return raw(x, y, 0, 0);
+			}
+
+			let (before, after) = (raw(x, y, -dx, -dy), raw(x, y, dx, dy));
+			let curvature = 2.0 * raw(x, y, 0, 0) - raw(x, y, -2 * dx, -2 * dy) - raw(x, y, 2 * dx, 2 * dy);
+			let estimate = (before + after) / 2.0 + curvature / 4.0;
+
+			estimate.max(before.min(after)).min(before.max(after))
+		}).collect();
+
+		// Red and blue are the green plus the mean difference between the color
+		// and green at the closest photosites of it. Those are the direct
+		// neighbours where there are any, and the diagonals where there aren't.
+		(0..meta.width * meta.height).map(|i| {
+			let (x, y) = meta.itoxy(i as usize);
+			let current = meta.color_at_xy(x, y);
+			let mut rgb = [0.0; 3];
+			rgb[Color::Green as usize] = green[i as usize];
+
+			for &color in [Color::Red, Color::Blue].iter() {
+				if color == current {
+					rgb[color as usize] = raw(x, y, 0, 0);
+					continue;
+				}
+
+				let difference = |offsets: &[(i32, i32)]| {
+					let differences: Vec<f32> = offsets.iter()
+						.map(|&(ox, oy)| mirrored(meta, x, y, ox, oy))
+						.filter(|&(nx, ny)| meta.color_at_xy(nx, ny) == color)
+						.map(|(nx, ny)| img.component(nx, ny, color) - green[meta.xytoi(nx, ny)])
+						.collect();
+
+					if differences.is_empty() {
+						None
+					} else {
+						Some(differences.iter().sum::<f32>() / differences.len() as f32)
+					}
+				};
+
+				let difference = difference(&DIRECT).or_else(|| difference(&DIAGONAL)).unwrap_or(0.0);
+				rgb[color as usize] = green[i as usize] + difference;
+			}
+
+			rgb
+		}).collect()
+	}
+
+	// How many of each pixel's direct neighbours are close to it in lightness
+	// and color, for both directions. What counts as close is the smallest
+	// of the differences along the direction each was interpolated in.
+	fn homogeneity(meta: &Metadata, horizontal: &[[f32; 3]], vertical: &[[f32; 3]]) -> (Vec<u32>, Vec<u32>) {
+		let mut horizontal_homogeneity = vec![0; horizontal.len()];
+		let mut vertical_homogeneity = vec![0; vertical.len()];
+
+		for i in 0..horizontal.len() {
+			let (x, y) = meta.itoxy(i);
+
+			let differences = |lab: &[[f32; 3]]| {
+				let here = lab[i];
+				let mut lightness = [0.0; 4];
+				let mut chroma = [0.0; 4];
+
+				for (k, &(dx, dy)) in DIRECT.iter().enumerate() {
+					let (nx, ny) = mirrored(meta, x, y, dx, dy);
+					let there = lab[meta.xytoi(nx, ny)];
+
+					lightness[k] = (here[0] - there[0]).abs();
+					chroma[k] = (here[1] - there[1]).powi(2) + (here[2] - there[2]).powi(2);
+				}
+
+				(lightness, chroma)
+			};
+
+			let (horizontal_lightness, horizontal_chroma) = differences(horizontal);
+			let (vertical_lightness, vertical_chroma) = differences(vertical);
+
+			let lightness_epsilon = f32::min(
+				horizontal_lightness[0].max(horizontal_lightness[1]),
+				vertical_lightness[2].max(vertical_lightness[3])
+			);
+			let chroma_epsilon = f32::min(
+				horizontal_chroma[0].max(horizontal_chroma[1]),
+				vertical_chroma[2].max(vertical_chroma[3])
+			);
+
+			let count = |lightness: [f32; 4], chroma: [f32; 4]| {
+				(0..4).filter(|&k| lightness[k] <= lightness_epsilon && chroma[k] <= chroma_epsilon).count() as u32
+			};
+
+			horizontal_homogeneity[i] = count(horizontal_lightness, horizontal_chroma);
+			vertical_homogeneity[i] = count(vertical_lightness, vertical_chroma);
+		}
+
+		(horizontal_homogeneity, vertical_homogeneity)
+	}
+
+	fn lab(to_xyz: &Matrix3, white: [f64; 3], rgb: [f32; 3]) -> [f32; 3] {
+		let xyz = color::multiply(to_xyz, [rgb[0] as f64, rgb[1] as f64, rgb[2] as f64]);
+		color::xyz_to_lab(xyz, white).map(|value| value as f32)
+	}
+}
@@ -0,0 +1,290 @@
+use super::{bayer_only, bayer_only_interpolate, combine, expand, mirrored, refine, sample, sensor_plane, Demosaic};
+use crate::image::{Color, Image, Metadata, Rgb, Sensor};
+
+// Aliasing Minimization and Zipper Elimination, after Emil Martinec's
+// algorithm from RawTherapee.
+//
+// Green is estimated horizontally and vertically with Hamilton-Adams color
+// difference interpolation, falling back to a ratio estimate where that
+// overshoots the greens beside it. The two are mixed by how much the color
+// difference varies along each direction, as it's smooth along edges and
+// rough across them. Where the greens show texture at the Nyquist frequency,
+// which can't be told apart from edges in either direction, green is taken
+// from all four directions instead. Red and blue come from color differences,
+// on the diagonals and then at the greens.
+
+// Keeps the divisions sane in flat, black areas
+const EPSILON: f32 = 1e-5;
+
+// How much of the difference between the vertical and horizontal pairs of
+// greens has to flip sign between neighbouring photosites to count as Nyquist
+// texture
+const NYQUIST_RATIO: f32 = 0.9;
+
+const AXES: [(i32, i32); 2] = [(1, 0), (0, 1)];
+
+/// See [Interpolation::Amaze](super::Interpolation::Amaze)
+pub struct Amaze;
+
+impl Demosaic for Amaze {
+	fn demosaic(&self, sensor: Image<Sensor, f32>) -> Image<Rgb, f32> {
+		self.demosaic_expanded(expand(sensor))
+	}
+
+	fn demosaic_expanded(&self, img: Image<Rgb, f32>) -> Image<Rgb, f32> {
+		bayer_only_interpolate(img, Self::interpolate)
+	}
+
+	// Green reads six pixels out through the direction weights, red and blue
+	// at red and blue two more, and red and blue at green three past those
+	fn reach(&self, meta: &Metadata) -> Option<u32> {
+		bayer_only(meta, 11)
+	}
+}
+
+impl Amaze {
+	fn interpolate(img: &mut Image<Rgb, f32>) {
+		let cfa = sensor_plane(img);
+		let meta = &img.meta;
+
+		// Green along each axis at red and blue, and the green minus the other
+		// color along each axis everywhere
+		let mut greens = [cfa.clone(), cfa.clone()];
+		let mut differences = [vec![0.0; cfa.len()], vec![0.0; cfa.len()]];
+		for i in 0..cfa.len() {
+			let (x, y) = meta.itoxy(i);
+			let is_green = meta.color_at_xy(x, y) == Color::Green;
+
+			for (axis, &(dx, dy)) in AXES.iter().enumerate() {
+				if is_green {
+					let other = (sample(meta, &cfa, x, y, -dx, -dy) + sample(meta, &cfa, x, y, dx, dy)) / 2.0
+						+ (2.0 * cfa[i] - sample(meta, &cfa, x, y, -2 * dx, -2 * dy) - sample(meta, &cfa, x, y, 2 * dx, 2 * dy)) / 4.0;
+
+					differences[axis][i] = cfa[i] - other;
+				} else {
+					greens[axis][i] = Self::green_along(meta, &cfa, x, y, (dx, dy));
+					differences[axis][i] = greens[axis][i] - cfa[i];
+				}
+			}
+		}
+
+		// How much to use horizontal over vertical, from how much the color
+		// difference varies along each
+		let horizontal_weights: Vec<f32> = (0..cfa.len()).map(|i| {
+			let (x, y) = meta.itoxy(i);
+			let horizontal = Self::variance(meta, &differences[0], x, y, AXES[0]);
+			let vertical = Self::variance(meta, &differences[1], x, y, AXES[1]);
+
+			(vertical + EPSILON) / (horizontal + vertical + 2.0 * EPSILON)
+		}).collect();
+
+		let nyquist = Self::nyquist(meta, &cfa);
+
+		let mut green = cfa.clone();
+		for (i, out) in green.iter_mut().enumerate() {
+			let (x, y) = meta.itoxy(i);
+			if meta.color_at_xy(x, y) == Color::Green {
+				continue;
+			}
+
+			*out = if nyquist[i] {
+				Self::green_area(meta, &cfa, x, y)
+			} else {
+				let weight = refine(meta, &horizontal_weights, x, y);
+				weight * greens[0][i] + (1.0 - weight) * greens[1][i]
+			};
+		}
+
+		// Red at blue and blue at red from the diagonal color differences
+		let mut planes = [cfa.clone(), green.clone(), cfa.clone()];
+		for i in 0..cfa.len() {
+			let (x, y) = meta.itoxy(i);
+			let current = meta.color_at_xy(x, y);
+			if current == Color::Green {
+				continue;
+			}
+
+			let color = if current == Color::Red { Color::Blue } else { Color::Red };
+			let estimate = |dx: i32, dy: i32| {
+				let gradient = EPSILON
+					+ (sample(meta, &cfa, x, y, dx, dy) - sample(meta, &cfa, x, y, -dx, -dy)).abs()
+					+ (sample(meta, &cfa, x, y, dx, dy) - sample(meta, &cfa, x, y, 3 * dx, 3 * dy)).abs()
+					+ (green[i] - sample(meta, &green, x, y, 2 * dx, 2 * dy)).abs();
+
+				(sample(meta, &cfa, x, y, dx, dy) - sample(meta, &green, x, y, dx, dy), gradient)
+			};
+
+			let (falling_down, falling_up) = (estimate(1, 1), estimate(-1, -1));
+			let (rising_up, rising_down) = (estimate(1, -1), estimate(-1, 1));
+
+			// Each diagonal counts for as much as the other changes
+			let falling_gradient = falling_down.1 + falling_up.1;
+			let rising_gradient = rising_up.1 + rising_down.1;
+			let weight = falling_gradient / (falling_gradient + rising_gradient);
+
+			let difference = weight * combine(rising_up, rising_down)
+				+ (1.0 - weight) * combine(falling_up, falling_down);
+
+			planes[color as usize][i] = green[i] + difference;
+		}
+
+		// Red and blue at green, from the color differences on either side
+		for i in 0..cfa.len() {
+			let (x, y) = meta.itoxy(i);
+			if meta.color_at_xy(x, y) != Color::Green {
+				continue;
+			}
+
+			let weight = refine(meta, &horizontal_weights, x, y);
+			for &color in [Color::Red, Color::Blue].iter() {
+				let plane = &planes[color as usize];
+				let estimate = |dx: i32, dy: i32| {
+					let gradient = EPSILON
+						+ (green[i] - sample(meta, &green, x, y, 2 * dx, 2 * dy)).abs()
+						+ (sample(meta, plane, x, y, dx, dy) - sample(meta, plane, x, y, -dx, -dy)).abs();
+
+					(sample(meta, plane, x, y, dx, dy) - sample(meta, &green, x, y, dx, dy), gradient)
+				};
+
+				let horizontal = combine(estimate(-1, 0), estimate(1, 0));
+				let vertical = combine(estimate(0, -1), estimate(0, 1));
+
+				let value = green[i] + weight * horizontal + (1.0 - weight) * vertical;
+				planes[color as usize][i] = value;
+			}
+		}
+
+		for pix in img.pixel_range() {
+			let current = img.meta.color_at_index(pix);
+
+			for &color in [Color::Red, Color::Green, Color::Blue].iter() {
+				if color != current {
+					let value = planes[color as usize][pix];
+					img.set_component(pix, color, value);
+				}
+			}
+		}
+	}
+
+	// The gradient on the side of x,y that `direction` points to
+	fn gradient(meta: &Metadata, cfa: &[f32], x: u32, y: u32, (dx, dy): (i32, i32)) -> f32 {
+		EPSILON
+			+ (sample(meta, cfa, x, y, dx, dy) - sample(meta, cfa, x, y, -dx, -dy)).abs()
+			+ (sample(meta, cfa, x, y, 0, 0) - sample(meta, cfa, x, y, 2 * dx, 2 * dy)).abs()
+			+ (sample(meta, cfa, x, y, dx, dy) - sample(meta, cfa, x, y, 3 * dx, 3 * dy)).abs()
+	}
+
+	// Hamilton-Adams estimate of green from the side `direction` points to
+	fn green_from(meta: &Metadata, cfa: &[f32], x: u32, y: u32, (dx, dy): (i32, i32)) -> f32 {
+		sample(meta, cfa, x, y, dx, dy)
+			+ (sample(meta, cfa, x, y, 0, 0) - sample(meta, cfa, x, y, 2 * dx, 2 * dy)) / 2.0
+	}
+
+	// Green at a red or blue photosite along an axis. The color difference
+	// estimate is used unless it overshoots the two greens beside it, as it
+	// does near highlights and on sharp colored edges, where the ratio
+	// estimate is used if that overshoots less.
+	fn green_along(meta: &Metadata, cfa: &[f32], x: u32, y: u32, (dx, dy): (i32, i32)) -> f32 {
+		let before = (Self::green_from(meta, cfa, x, y, (-dx, -dy)), Self::gradient(meta, cfa, x, y, (-dx, -dy)));
+		let after = (Self::green_from(meta, cfa, x, y, (dx, dy)), Self::gradient(meta, cfa, x, y, (dx, dy)));
+		let difference = combine(before, after);
+
+		let (left, right) = (sample(meta, cfa, x, y, -dx, -dy), sample(meta, cfa, x, y, dx, dy));
+		let (low, high) = (left.min(right), left.max(right));
+		let overshoot = |value: f32| (low - value).max(value - high).max(0.0);
+
+		if overshoot(difference) == 0.0 {
+			return difference;
+		}
+
+		let center = sample(meta, cfa, x, y, 0, 0);
+		let ratio_from = |side: f32, far: f32| {
+			let sum = center + far;
+			if sum > EPSILON {
+				side * (2.0 * center / sum).min(4.0)
+			} else {
+				side
+			}
+		};
+
+		let ratio = combine(
+			(ratio_from(left, sample(meta, cfa, x, y, -2 * dx, -2 * dy)), before.1),
+			(ratio_from(right, sample(meta, cfa, x, y, 2 * dx, 2 * dy)), after.1)
+		);
+
+		if overshoot(ratio) < overshoot(difference) {
+			ratio
+		} else {
+			difference
+		}
+	}
+
+	// Green at a red or blue photosite from all four sides, for Nyquist texture
+	fn green_area(meta: &Metadata, cfa: &[f32], x: u32, y: u32) -> f32 {
+		let mut sum = 0.0;
+		let mut weights = 0.0;
+
+		for &(dx, dy) in [(-1, 0), (1, 0), (0, -1), (0, 1)].iter() {
+			let weight = 1.0 / Self::gradient(meta, cfa, x, y, (dx, dy)).powi(2);
+			sum += weight * Self::green_from(meta, cfa, x, y, (dx, dy));
+			weights += weight;
+		}
+
+		sum / weights
+	}
+
+	// How much the color difference varies over the five pixels along `axis`
+	fn variance(meta: &Metadata, differences: &[f32], x: u32, y: u32, (dx, dy): (i32, i32)) -> f32 {
+		let values: Vec<f32> = (-2..=2).map(|k| sample(meta, differences, x, y, k * dx, k * dy)).collect();
+		let mean = values.iter().sum::<f32>() / 5.0;
+
+		values.iter().map(|value| (value - mean).powi(2)).sum()
+	}
+
+	// Red and blue photosites in Nyquist texture. There, the difference between
+	// the vertical and horizontal pairs of greens around a photosite flips sign
+	// at the diagonal neighbours, where along an edge it stays about the same.
+	// Most of the photosites in the 5x5 have to agree so noise doesn't count.
+	fn nyquist(meta: &Metadata, cfa: &[f32]) -> Vec<bool> {
+		let pair_difference: Vec<f32> = (0..cfa.len()).map(|i| {
+			let (x, y) = meta.itoxy(i);
+			let at = |dx: i32, dy: i32| sample(meta, cfa, x, y, dx, dy);
+
+			(at(0, -1) + at(0, 1)) - (at(-1, 0) + at(1, 0))
+		}).collect();
+
+		let flips: Vec<bool> = (0..cfa.len()).map(|i| {
+			let (x, y) = meta.itoxy(i);
+			let here = pair_difference[i];
+			let diagonals = [(-1, -1), (1, -1), (-1, 1), (1, 1)].iter()
+				.map(|&(dx, dy)| sample(meta, &pair_difference, x, y, dx, dy))
+				.sum::<f32>() / 4.0;
+
+			let magnitude = here.abs() + diagonals.abs();
+			magnitude > EPSILON && (here - diagonals).abs() > NYQUIST_RATIO * magnitude
+		}).collect();
+
+		(0..cfa.len()).map(|i| {
+			let (x, y) = meta.itoxy(i);
+			if meta.color_at_xy(x, y) == Color::Green {
+				return false;
+			}
+
+			let mut count = 0;
+			let mut total = 0;
+			for dy in -2..=2 {
+				for dx in -2..=2 {
+					let (nx, ny) = mirrored(meta, x, y, dx, dy);
+					if meta.color_at_xy(nx, ny) != Color::Green {
+						total += 1;
+						if flips[meta.xytoi(nx, ny)] {
+							count += 1;
+						}
+					}
+				}
+			}
+
+			count * 4 > total * 3
+		}).collect()
+	}
+}
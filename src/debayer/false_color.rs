@@ -0,0 +1,33 @@
+use super::sample;
+use crate::image::{Color, Image, Rgb};
+use std::cmp::Ordering;
+
+/// Remove false color and color moiré from a demosaiced image. The
+/// differences between red and green, and blue and green, change slowly in
+/// real images, so each is put through a 3x3 median, `iterations` times, and
+/// red and blue are made again from green and the filtered differences. Green
+/// is left alone, so detail is kept.
+pub fn suppress_false_color(img: &mut Image<Rgb, f32>, iterations: u32) {
+	let meta = &img.meta;
+
+	for _ in 0..iterations {
+		for &color in [Color::Red, Color::Blue].iter() {
+			let differences: Vec<f32> = img.data.chunks(3)
+				.map(|rgb| rgb[color as usize] - rgb[Color::Green as usize])
+				.collect();
+
+			for (i, rgb) in img.data.chunks_mut(3).enumerate() {
+				let (x, y) = meta.itoxy(i);
+
+				let mut window = [0.0; 9];
+				for (k, value) in window.iter_mut().enumerate() {
+					let (dx, dy) = (k as i32 % 3 - 1, k as i32 / 3 - 1);
+					*value = sample(meta, &differences, x, y, dx, dy);
+				}
+				window.sort_unstable_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+
+				rgb[color as usize] = rgb[Color::Green as usize] + window[4];
+			}
+		}
+	}
+}
@@ -0,0 +1,102 @@
+use super::{expand, mirrored, Bilinear, Demosaic};
+use crate::image::{Color, Image, Metadata, Rgb, Sensor};
+
+// Malvar, He and Cutler's "High-quality linear interpolation for demosaicing
+// of Bayer-patterned color images".
+//
+// Bilinear interpolation corrected by the Laplacian of the photosite's own
+// color, which is where most of the edge information is. Each kernel is a
+// list of offsets and weights, and they all sum to 8.
+
+// Green at a red or blue photosite
+const GREEN_AT_OTHER: [(i32, i32, f32); 9] = [
+	(0, -2, -1.0),
+	(0, -1, 2.0),
+	(-2, 0, -1.0), (-1, 0, 2.0), (0, 0, 4.0), (1, 0, 2.0), (2, 0, -1.0),
+	(0, 1, 2.0),
+	(0, 2, -1.0)
+];
+
+// Red or blue at a green photosite where that color is to the left and right
+const BESIDE: [(i32, i32, f32); 11] = [
+	(0, -2, 0.5),
+	(-1, -1, -1.0), (1, -1, -1.0),
+	(-2, 0, -1.0), (-1, 0, 4.0), (0, 0, 5.0), (1, 0, 4.0), (2, 0, -1.0),
+	(-1, 1, -1.0), (1, 1, -1.0),
+	(0, 2, 0.5)
+];
+
+// Red or blue at a green photosite where that color is above and below
+const ABOVE: [(i32, i32, f32); 11] = [
+	(0, -2, -1.0),
+	(-1, -1, -1.0), (0, -1, 4.0), (1, -1, -1.0),
+	(-2, 0, 0.5), (0, 0, 5.0), (2, 0, 0.5),
+	(-1, 1, -1.0), (0, 1, 4.0), (1, 1, -1.0),
+	(0, 2, -1.0)
+];
+
+// Red at a blue photosite, or blue at a red one
+const DIAGONAL: [(i32, i32, f32); 9] = [
+	(0, -2, -1.5),
+	(-1, -1, 2.0), (1, -1, 2.0),
+	(-2, 0, -1.5), (0, 0, 6.0), (2, 0, -1.5),
+	(-1, 1, 2.0), (1, 1, 2.0),
+	(0, 2, -1.5)
+];
+
+/// See [Interpolation::Malvar](super::Interpolation::Malvar)
+pub struct Malvar;
+
+impl Demosaic for Malvar {
+	fn demosaic(&self, sensor: Image<Sensor, f32>) -> Image<Rgb, f32> {
+		self.demosaic_expanded(expand(sensor))
+	}
+
+	fn demosaic_expanded(&self, mut img: Image<Rgb, f32>) -> Image<Rgb, f32> {
+		Self::interpolate(&mut img);
+		img
+	}
+
+	fn reach(&self, _meta: &Metadata) -> Option<u32> {
+		Some(2)
+	}
+}
+
+impl Malvar {
+	// The kernels are made for the Bayer layout, so anything else is done with
+	// Bilinear
+	pub(super) fn interpolate(img: &mut Image<Rgb, f32>) {
+		if !img.meta.cfa.is_bayer() {
+			Bilinear::interpolate(img);
+			return;
+		}
+
+		for pix in img.pixel_range() {
+			let (x, y) = img.meta.itoxy(pix);
+			let current = img.meta.color_at_xy(x, y);
+
+			for &color in [Color::Red, Color::Green, Color::Blue].iter() {
+				if color == current {
+					continue;
+				}
+
+				let kernel: &[(i32, i32, f32)] = if color == Color::Green {
+					&GREEN_AT_OTHER
+				} else if current != Color::Green {
+					&DIAGONAL
+				} else if img.meta.color_at_xy(mirrored(&img.meta, x, y, 1, 0).0, y) == color {
+					&BESIDE
+				} else {
+					&ABOVE
+				};
+
+				let value: f32 = kernel.iter().map(|&(dx, dy, weight)| {
+					let (nx, ny) = mirrored(&img.meta, x, y, dx, dy);
+					weight * img.component(nx, ny, img.meta.color_at_xy(nx, ny))
+				}).sum();
+
+				img.set_component(pix, color, value / 8.0);
+			}
+		}
+	}
+}
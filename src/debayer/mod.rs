@@ -0,0 +1,1019 @@
+mod ahd;
+mod amaze;
+mod false_color;
+mod malvar;
+mod rcd;
+mod superpixel;
+mod vng;
+
+use crate::image::{Color, Image, Metadata, Rgb, Sensor};
+use rand::{Rng, RngCore, SeedableRng};
+use rand::rngs::StdRng;
+pub use self::ahd::Ahd;
+pub use self::amaze::Amaze;
+pub use self::false_color::suppress_false_color;
+pub use self::malvar::Malvar;
+pub use self::rcd::Rcd;
+pub use self::superpixel::Superpixel;
+pub use self::vng::Vng;
+
+/// A demosaicing algorithm. It's given the sensor data, with the CFA and
+/// everything else about the camera in its metadata, and makes an RGB image
+/// from it. That's usually the same size, but doesn't have to be as long as
+/// the metadata says what it is.
+///
+/// The built-in algorithms are the structs in this module, and
+/// [Interpolation] picks between them. Anything else can implement this to
+/// be used with [Debayer::demosaic] too.
+pub trait Demosaic {
+	fn demosaic(&self, sensor: Image<Sensor, f32>) -> Image<Rgb, f32>;
+
+	/// Interpolate an image like [expand] makes, with each photosite in its
+	/// color's component and zero in the other two. [Debayer::strips] writes
+	/// each strip's photosites straight from the sensor data into one buffer
+	/// it reuses and calls this with it. The default takes the photosites
+	/// back out with [sensor_plane] and calls [Demosaic::demosaic].
+	fn demosaic_expanded(&self, img: Image<Rgb, f32>) -> Image<Rgb, f32> {
+		let data = sensor_plane(&img);
+		self.demosaic(Image { kind: Sensor {}, data, meta: img.meta })
+	}
+
+	/// How many pixels away from a pixel the algorithm reads to work it out,
+	/// counting through anything it interpolates on the way. If it's known,
+	/// [Debayer::strips] can do a few rows at a time. None, the default, is
+	/// for algorithms that need the whole image.
+	fn reach(&self, _meta: &Metadata) -> Option<u32> {
+		None
+	}
+}
+
+/// Spread sensor data out into an RGB image. Each pixel gets its photosite in
+/// the component for its color and zero in the other two, ready for those to
+/// be interpolated.
+pub fn expand(mut rimg: Image<Sensor, f32>) -> Image<Rgb, f32> {
+	let sensor_len = rimg.data.len();
+	rimg.data.resize(sensor_len * 3, 0.0);
+
+	// Backwards so nothing's overwritten before it's moved. The value is
+	// read first as the first pixel is both where it's read and written.
+	for i in (0..sensor_len).rev() {
+		let light = rimg.data[i];
+
+		match rimg.meta.color_at_index(i) {
+			Color::Red => {
+				rimg.data[i*3] = light;
+				rimg.data[i*3+1] = 0.0;
+				rimg.data[i*3+2] = 0.0;
+			},
+			Color::Green => {
+				rimg.data[i*3] = 0.0;
+				rimg.data[i*3+1] = light;
+				rimg.data[i*3+2] = 0.0;
+			},
+			Color::Blue => {
+				rimg.data[i*3] = 0.0;
+				rimg.data[i*3+1] = 0.0;
+				rimg.data[i*3+2] = light;
+			}
+		}
+	}
+
+	Image {
+		kind: Rgb {},
+		data: rimg.data,
+		meta: rimg.meta
+	}
+}
+
+pub struct Debayer {
+	sensor: Image<Sensor, f32>,
+	false_color_iterations: u32
+}
+
+impl Debayer {
+	pub fn new(rimg: Image<Sensor, f32>) -> Self {
+		Self {
+			sensor: rimg,
+			false_color_iterations: 0
+		}
+	}
+
+	/// Suppress false color after demosaicing, with this many passes of
+	/// [suppress_false_color]. Zero, the default, is off. One to three
+	/// passes is usually enough.
+	pub fn false_color(mut self, iterations: u32) -> Self {
+		self.false_color_iterations = iterations;
+		self
+	}
+
+	pub fn interpolate(self, interpolation: Interpolation) -> Image<Rgb, f32> {
+		self.demosaic(&interpolation)
+	}
+
+	/// Demosaic with any algorithm, either one of ours or your own. Takes
+	/// trait objects as well.
+	pub fn demosaic<D: Demosaic + ?Sized>(self, algorithm: &D) -> Image<Rgb, f32> {
+		let mut img = algorithm.demosaic(self.sensor);
+		suppress_false_color(&mut img, self.false_color_iterations);
+		img
+	}
+
+	/// Demosaic like [Debayer::demosaic], but `rows` rows at a time. It's the
+	/// same as doing the whole image at once, apart from nearest neighbour
+	/// choosing at random, seeded or not.
+	///
+	/// The sensor data is grown to the size of the RGB output and the strips
+	/// are written over it from the bottom up, so the output is all the memory
+	/// the image itself takes. On top of that there's one strip with its
+	/// [reach](Demosaic::reach) either side, and whatever the algorithm needs
+	/// to work on it, where doing the whole image at once needs that for all
+	/// of it. That's several times the output for AHD, RCD and AMaZE. Strips
+	/// are made at least half the reach tall so none is written over sensor
+	/// rows a strip above it still reads.
+	pub fn demosaic_strips<D: Demosaic + ?Sized>(self, algorithm: &D, rows: u32) -> Image<Rgb, f32> {
+		let reach = match self.reach(algorithm) {
+			Some(reach) => reach,
+			None => return self.demosaic(algorithm)
+		};
+
+		let iterations = self.false_color_iterations;
+		let Image { mut data, mut meta, .. } = self.sensor;
+		let (width, height) = (meta.width as usize, meta.height);
+		let cfa = meta.cfa.clone();
+
+		let rows = rows.max(reach.div_ceil(2)).max(1);
+		let sensor_len = data.len();
+		data.resize(sensor_len * 3, 0.0);
+
+		let starts: Vec<u32> = (0..height).step_by(rows as usize).collect();
+		let mut window = Vec::new();
+		for &y in starts.iter().rev() {
+			let end = (y + rows).min(height);
+			let top = y.saturating_sub(reach);
+			let bottom = (end + reach).min(height);
+
+			// The metadata's moved in and out of each strip as it can't be cloned
+			meta.height = bottom - top;
+			meta.cfa = cfa.offset(0, top);
+			let sensor = &data[top as usize * width..bottom as usize * width];
+			let rgb = Self::strip(algorithm, sensor, meta, window, iterations);
+
+			let start = (y - top) as usize * width * 3;
+			let len = (end - y) as usize * width * 3;
+			data[y as usize * width * 3..end as usize * width * 3].copy_from_slice(&rgb.data[start..start + len]);
+
+			meta = rgb.meta;
+			window = rgb.data;
+		}
+
+		meta.height = height;
+		meta.cfa = cfa;
+		Image {
+			kind: Rgb {},
+			data,
+			meta
+		}
+	}
+
+	/// Demosaic `rows` rows at a time, giving each strip to `f` with the row
+	/// it starts at. Returns the metadata.
+	///
+	/// It's the least memory if the strips can be written out as they come.
+	/// The RGB output is never held at once, only the sensor data and one
+	/// strip with its reach either side, and whatever the algorithm needs to
+	/// work on it.
+	///
+	/// Each strip is read with as many rows around it as the algorithm's
+	/// [reach](Demosaic::reach), and one more for every pass of false color
+	/// suppression. Algorithms without one are given the whole image and it's
+	/// one strip.
+	pub fn strips<D, F>(self, algorithm: &D, rows: u32, mut f: F) -> Metadata
+	where
+		D: Demosaic + ?Sized,
+		F: FnMut(u32, &[f32])
+	{
+		let reach = match self.reach(algorithm) {
+			Some(reach) => reach,
+			None => {
+				let img = self.demosaic(algorithm);
+				f(0, &img.data);
+				return img.meta;
+			}
+		};
+
+		let iterations = self.false_color_iterations;
+		let Image { data, mut meta, .. } = self.sensor;
+		let (width, height) = (meta.width as usize, meta.height);
+		let cfa = meta.cfa.clone();
+
+		let mut window = Vec::new();
+		let mut y = 0;
+		while y < height {
+			let end = (y + rows.max(1)).min(height);
+			let top = y.saturating_sub(reach);
+			let bottom = (end + reach).min(height);
+
+			meta.height = bottom - top;
+			meta.cfa = cfa.offset(0, top);
+			let sensor = &data[top as usize * width..bottom as usize * width];
+			let rgb = Self::strip(algorithm, sensor, meta, window, iterations);
+
+			let start = (y - top) as usize * width * 3;
+			f(y, &rgb.data[start..start + (end - y) as usize * width * 3]);
+
+			meta = rgb.meta;
+			window = rgb.data;
+			y = end;
+		}
+
+		meta.height = height;
+		meta.cfa = cfa;
+		meta
+	}
+
+	// How many rows either side of a strip it needs, None if it can't be done
+	// in strips
+	fn reach<D: Demosaic + ?Sized>(&self, algorithm: &D) -> Option<u32> {
+		algorithm.reach(&self.sensor.meta).map(|reach| reach + self.false_color_iterations)
+	}
+
+	// Demosaic the rows of sensor data `meta` describes. Their photosites are
+	// written into `window`, which is reused from strip to strip, and it's
+	// interpolated in place.
+	fn strip<D: Demosaic + ?Sized>(algorithm: &D, sensor: &[f32], meta: Metadata, mut window: Vec<f32>, iterations: u32) -> Image<Rgb, f32> {
+		window.clear();
+		for (i, &light) in sensor.iter().enumerate() {
+			let mut rgb = [0.0; 3];
+			rgb[meta.color_at_index(i) as usize] = light;
+			window.extend_from_slice(&rgb);
+		}
+
+		let mut rgb = algorithm.demosaic_expanded(Image {
+			kind: Rgb {},
+			data: window,
+			meta
+		});
+		suppress_false_color(&mut rgb, iterations);
+		rgb
+	}
+}
+
+pub enum Interpolation {
+	None,
+	/// Copies from a random one of the closest photosites, so it's different
+	/// every time
+	NearestNeighbor,
+	/// Nearest neighbour that always copies from the same photosite
+	NearestNeighborFixed,
+	/// Nearest neighbour with a random generator seeded with this
+	NearestNeighborSeeded(u64),
+	Bilinear,
+	/// Green first, then red and blue from color differences, then a pass
+	/// refining green. Made for X-Trans but works with any CFA.
+	ThreePass,
+	/// Variable Number of Gradients. Interpolates along the directions the
+	/// image changes least in, which avoids most of the zippering bilinear
+	/// leaves on edges. Bayer only, other CFAs get ThreePass.
+	Vng,
+	/// Adaptive Homogeneity-Directed. Interpolates horizontally and
+	/// vertically and keeps whichever is smoother in CIELab around each pixel.
+	/// Bayer only, other CFAs get ThreePass.
+	Ahd,
+	/// Malvar-He-Cutler. Bilinear with a correction from the Laplacian of the
+	/// photosite's own color, which keeps edges sharp without the fringing and
+	/// costs about as much. Bayer only, other CFAs get Bilinear.
+	Malvar,
+	/// Ratio Corrected Demosaicing. Green from directional estimates scaled by
+	/// the local luminance ratio, then red and blue from color differences.
+	/// Very few artifacts on edges and round features. Bayer only, other CFAs
+	/// get ThreePass.
+	Rcd,
+	/// Aliasing Minimization and Zipper Elimination. Directional green mixed
+	/// by how smooth the color difference is each way, with fine texture
+	/// detected and handled separately. Best on detailed images. Bayer only,
+	/// other CFAs get ThreePass.
+	Amaze,
+	/// Every 2x2 block becomes one pixel, with the two greens averaged, for an
+	/// image half the width and height. It's very fast and made for previews.
+	/// X-Trans is done in 3x3 blocks for a third of the size.
+	Superpixel
+}
+
+impl Interpolation {
+	// The algorithm for each variant, which the Demosaic methods all go to
+	fn algorithm(&self) -> Box<dyn Demosaic> {
+		match self {
+			Interpolation::None => Box::new(Uninterpolated),
+			Interpolation::NearestNeighbor => Box::new(NearestNeighbor::new(NeighborChoice::Random)),
+			Interpolation::NearestNeighborFixed => Box::new(NearestNeighbor::new(NeighborChoice::Fixed)),
+			Interpolation::NearestNeighborSeeded(seed) => Box::new(NearestNeighbor::new(NeighborChoice::Seeded(*seed))),
+			Interpolation::Bilinear => Box::new(Bilinear),
+			Interpolation::ThreePass => Box::new(ThreePass),
+			Interpolation::Vng => Box::new(Vng),
+			Interpolation::Ahd => Box::new(Ahd),
+			Interpolation::Malvar => Box::new(Malvar),
+			Interpolation::Rcd => Box::new(Rcd),
+			Interpolation::Amaze => Box::new(Amaze),
+			Interpolation::Superpixel => Box::new(Superpixel)
+		}
+	}
+}
+
+impl Demosaic for Interpolation {
+	fn demosaic(&self, sensor: Image<Sensor, f32>) -> Image<Rgb, f32> {
+		self.algorithm().demosaic(sensor)
+	}
+
+	fn demosaic_expanded(&self, img: Image<Rgb, f32>) -> Image<Rgb, f32> {
+		self.algorithm().demosaic_expanded(img)
+	}
+
+	fn reach(&self, meta: &Metadata) -> Option<u32> {
+		self.algorithm().reach(meta)
+	}
+}
+
+// Interpolation::None, the photosites spread out with nothing filled in
+struct Uninterpolated;
+
+impl Demosaic for Uninterpolated {
+	fn demosaic(&self, sensor: Image<Sensor, f32>) -> Image<Rgb, f32> {
+		expand(sensor)
+	}
+
+	fn demosaic_expanded(&self, img: Image<Rgb, f32>) -> Image<Rgb, f32> {
+		img
+	}
+
+	fn reach(&self, _meta: &Metadata) -> Option<u32> {
+		Some(0)
+	}
+}
+
+/// Which of the closest photosites nearest neighbour copies from when there's
+/// more than one
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum NeighborChoice {
+	/// A random one, different every run
+	Random,
+	/// Always the first, going clockwise from the one above. The output is the
+	/// same every run.
+	Fixed,
+	/// A random one from a generator seeded with this. The output is the same
+	/// every run with the same seed and version of the rand crate.
+	Seeded(u64)
+}
+
+// The offsets NearestNeighbor looks at, closest first: the direct neighbours
+// clockwise from above, the diagonals, and the ring two pixels out
+const NEIGHBOUR_RINGS: [&[(i32, i32)]; 3] = [
+	&[(0, -1), (1, 0), (0, 1), (-1, 0)],
+	&[(1, -1), (1, 1), (-1, 1), (-1, -1)],
+	&[
+		(-2, -2), (-1, -2), (0, -2), (1, -2), (2, -2),
+		(-2, -1), (2, -1),
+		(-2, 0), (2, 0),
+		(-2, 1), (2, 1),
+		(-2, 2), (-1, 2), (0, 2), (1, 2), (2, 2)
+	]
+];
+
+/// Each missing component is copied from one of the closest photosites of
+/// that color
+pub struct NearestNeighbor {
+	pub choice: NeighborChoice
+}
+
+impl Demosaic for NearestNeighbor {
+	fn demosaic(&self, sensor: Image<Sensor, f32>) -> Image<Rgb, f32> {
+		self.demosaic_expanded(expand(sensor))
+	}
+
+	fn demosaic_expanded(&self, mut img: Image<Rgb, f32>) -> Image<Rgb, f32> {
+		self.interpolate(&mut img);
+		img
+	}
+
+	fn reach(&self, _meta: &Metadata) -> Option<u32> {
+		Some(2)
+	}
+}
+
+impl NearestNeighbor {
+	pub fn new(choice: NeighborChoice) -> Self {
+		Self { choice }
+	}
+
+	fn interpolate(&self, cimg: &mut Image<Rgb, f32>) {
+		let mut rng: Option<Box<dyn RngCore>> = match self.choice {
+			NeighborChoice::Random => Some(Box::new(rand::thread_rng())),
+			NeighborChoice::Fixed => None,
+			NeighborChoice::Seeded(seed) => Some(Box::new(StdRng::seed_from_u64(seed)))
+		};
+
+		for pix in cimg.pixel_range() {
+			let current = cimg.meta.color_at_index(pix);
+
+			for &color in [Color::Red, Color::Green, Color::Blue].iter() {
+				if color != current {
+					let value = Self::get_component(cimg, color, pix, &mut rng);
+					cimg.set_component(pix, color, value);
+				}
+			}
+		}
+	}
+
+	// Picks a photosite of the wanted color out of the closest ones, at random
+	// if there's a generator and the first otherwise. The ring two pixels out
+	// is for X-Trans, which sometimes needs it at the edges.
+	fn get_component(cimg: &Image<Rgb, f32>, color: Color, i: usize, rng: &mut Option<Box<dyn RngCore>>) -> f32 {
+		let (x, y) = cimg.meta.itoxy(i);
+
+		for offsets in NEIGHBOUR_RINGS.iter() {
+			let mut candidates = [(0, 0); 16];
+			let mut count = 0;
+
+			for &(dx, dy) in offsets.iter() {
+				if let Some((nx, ny)) = neighbour(&cimg.meta, x, y, dx, dy) {
+					if cimg.meta.color_at_xy(nx, ny) == color {
+						candidates[count] = (nx, ny);
+						count += 1;
+					}
+				}
+			}
+
+			if count > 0 {
+				let index = match rng {
+					Some(rng) => rng.gen_range(0, count),
+					None => 0
+				};
+
+				let (nx, ny) = candidates[index];
+				return cimg.component(nx, ny, color);
+			}
+		}
+
+		0.0
+	}
+}
+
+/// Each missing component is the mean of the closest photosites of that color
+pub struct Bilinear;
+
+impl Demosaic for Bilinear {
+	fn demosaic(&self, sensor: Image<Sensor, f32>) -> Image<Rgb, f32> {
+		self.demosaic_expanded(expand(sensor))
+	}
+
+	fn demosaic_expanded(&self, mut img: Image<Rgb, f32>) -> Image<Rgb, f32> {
+		Self::interpolate(&mut img);
+		img
+	}
+
+	fn reach(&self, _meta: &Metadata) -> Option<u32> {
+		Some(2)
+	}
+}
+
+impl Bilinear {
+	// Every missing component is the average of the photosites of that color in
+	// the surrounding 3x3. For any of the Bayer layouts this is the direct
+	// neighbours or the diagonals, and only the neighbours that exist at edges.
+	// X-Trans can be missing a color in the 3x3 at the edges, so the 5x5 is
+	// used there.
+	fn interpolate(img: &mut Image<Rgb, f32>) {
+		for pix in img.pixel_range() {
+			let (x, y) = img.meta.itoxy(pix);
+			let current = img.meta.color_at_xy(x, y);
+
+			for &color in [Color::Red, Color::Green, Color::Blue].iter() {
+				if color != current {
+					let average = window_mean(img, x, y, 1, color, |value, _| value)
+						.or_else(|| window_mean(img, x, y, 2, color, |value, _| value));
+
+					if let Some(average) = average {
+						img.set_component(pix, color, average);
+					}
+				}
+			}
+		}
+	}
+}
+
+/// See [Interpolation::ThreePass]
+pub struct ThreePass;
+
+impl Demosaic for ThreePass {
+	fn demosaic(&self, sensor: Image<Sensor, f32>) -> Image<Rgb, f32> {
+		self.demosaic_expanded(expand(sensor))
+	}
+
+	fn demosaic_expanded(&self, mut img: Image<Rgb, f32>) -> Image<Rgb, f32> {
+		Self::interpolate(&mut img);
+		img
+	}
+
+	// Each pass reads up to two pixels away from what the last one made
+	fn reach(&self, _meta: &Metadata) -> Option<u32> {
+		Some(6)
+	}
+}
+
+impl ThreePass {
+	fn interpolate(img: &mut Image<Rgb, f32>) {
+		// Pass one: green at the red and blue photosites from the closest greens
+		for pix in img.pixel_range() {
+			let (x, y) = img.meta.itoxy(pix);
+
+			if img.meta.color_at_xy(x, y) != Color::Green {
+				if let Some(green) = Self::mean(img, x, y, Color::Green, |value, _| value) {
+					img.set_component(pix, Color::Green, green);
+				}
+			}
+		}
+
+		// Pass two: red and blue everywhere they're missing. The difference
+		// between a color and green changes slower than the color itself, so we
+		// average that and add it back to the green here.
+		for pix in img.pixel_range() {
+			let (x, y) = img.meta.itoxy(pix);
+			let current = img.meta.color_at_xy(x, y);
+			let green = img.component(x, y, Color::Green);
+
+			for &color in [Color::Red, Color::Blue].iter() {
+				if color != current {
+					let difference = Self::mean(img, x, y, color, |value, (nx, ny)| {
+						value - img.component(nx, ny, Color::Green)
+					});
+
+					if let Some(difference) = difference {
+						img.set_component(pix, color, green + difference);
+					}
+				}
+			}
+		}
+
+		// Pass three: refine the green from pass one using the color difference
+		// at the neighbouring greens, which all have the red and blue from pass
+		// two now. Whatever green moves by, the other interpolated color follows.
+		for pix in img.pixel_range() {
+			let (x, y) = img.meta.itoxy(pix);
+			let current = img.meta.color_at_xy(x, y);
+
+			if current == Color::Green {
+				continue;
+			}
+
+			let difference = Self::mean(img, x, y, Color::Green, |green, (nx, ny)| {
+				img.component(nx, ny, current) - green
+			});
+
+			if let Some(difference) = difference {
+				let other = if current == Color::Red { Color::Blue } else { Color::Red };
+				let old_green = img.component(x, y, Color::Green);
+				let new_green = img.component(x, y, current) - difference;
+
+				img.set_component(pix, Color::Green, new_green);
+				img.set_component(pix, other, img.component(x, y, other) + new_green - old_green);
+			}
+		}
+	}
+
+	// The mean of `f` over photosites of `color` near x,y, looking further out
+	// if there are none close by
+	fn mean<F>(img: &Image<Rgb, f32>, x: u32, y: u32, color: Color, f: F) -> Option<f32>
+	where F: Fn(f32, (u32, u32)) -> f32
+	{
+		window_mean(img, x, y, 1, color, &f).or_else(|| window_mean(img, x, y, 2, color, &f))
+	}
+}
+
+// VNG, AHD, RCD and AMaZE are written for the Bayer layout, where rows and
+// columns alternate two colors and the same color is always two photosites
+// away. Other CFAs are done with ThreePass, which works with any of them.
+// This is their reach, `bayer` for a Bayer CFA and ThreePass's otherwise.
+fn bayer_only(meta: &Metadata, bayer: u32) -> Option<u32> {
+	if meta.cfa.is_bayer() {
+		Some(bayer)
+	} else {
+		ThreePass.reach(meta)
+	}
+}
+
+// And this interpolates with `interpolate` for a Bayer CFA, ThreePass otherwise
+fn bayer_only_interpolate(mut img: Image<Rgb, f32>, interpolate: fn(&mut Image<Rgb, f32>)) -> Image<Rgb, f32> {
+	if img.meta.cfa.is_bayer() {
+		interpolate(&mut img);
+	} else {
+		ThreePass::interpolate(&mut img);
+	}
+
+	img
+}
+
+// The mean of `f`, called with the component and its coordinates, for every
+// photosite of `color` in the square window of `radius` around x,y. The
+// center pixel isn't included. None if there isn't any photosite of `color`.
+fn window_mean<F>(img: &Image<Rgb, f32>, x: u32, y: u32, radius: i32, color: Color, f: F) -> Option<f32>
+where F: Fn(f32, (u32, u32)) -> f32
+{
+	let mut sum = 0.0;
+	let mut count = 0;
+
+	for dy in -radius..=radius {
+		for dx in -radius..=radius {
+			if dx == 0 && dy == 0 {
+				continue;
+			}
+
+			if let Some((nx, ny)) = neighbour(&img.meta, x, y, dx, dy) {
+				if img.meta.color_at_xy(nx, ny) == color {
+					sum += f(img.component(nx, ny, color), (nx, ny));
+					count += 1;
+				}
+			}
+		}
+	}
+
+	if count == 0 {
+		None
+	} else {
+		Some(sum / count as f32)
+	}
+}
+
+// The coordinates of the pixel offset from x,y by dx,dy if it's in the image
+fn neighbour(meta: &Metadata, x: u32, y: u32, dx: i32, dy: i32) -> Option<(u32, u32)> {
+	let nx = x as i64 + dx as i64;
+	let ny = y as i64 + dy as i64;
+
+	if nx < 0 || ny < 0 || nx >= meta.width as i64 || ny >= meta.height as i64 {
+		None
+	} else {
+		Some((nx as u32, ny as u32))
+	}
+}
+
+// The coordinates of the pixel offset from x,y by dx,dy, reflected back into
+// the image where they'd be outside it. Reflecting moves by an even number of
+// pixels so it lands on the same color of a Bayer CFA.
+pub(crate) fn mirrored(meta: &Metadata, x: u32, y: u32, dx: i32, dy: i32) -> (u32, u32) {
+	let reflect = |value: i64, len: i64| {
+		let value = if value < 0 {
+			-value
+		} else if value >= len {
+			2 * (len - 1) - value
+		} else {
+			value
+		};
+
+		value.max(0).min(len - 1) as u32
+	};
+
+	(
+		reflect(x as i64 + dx as i64, meta.width as i64),
+		reflect(y as i64 + dy as i64, meta.height as i64)
+	)
+}
+
+// The value in `plane`, which has one per pixel, offset from x,y by dx,dy and
+// reflected back into the image like [mirrored]
+fn sample(meta: &Metadata, plane: &[f32], x: u32, y: u32, dx: i32, dy: i32) -> f32 {
+	let (nx, ny) = mirrored(meta, x, y, dx, dy);
+	plane[meta.xytoi(nx, ny)]
+}
+
+// The photosite value of every pixel, which is the one component Debayer::new
+// filled in
+fn sensor_plane(img: &Image<Rgb, f32>) -> Vec<f32> {
+	img.pixel_range().map(|pix| {
+		let (x, y) = img.meta.itoxy(pix);
+		img.component(x, y, img.meta.color_at_xy(x, y))
+	}).collect()
+}
+
+// Two estimates from opposite sides of a pixel, each with the gradient on its
+// side, mixed so the side that changes less counts for more
+fn combine((first, first_gradient): (f32, f32), (second, second_gradient): (f32, f32)) -> f32 {
+	(first * second_gradient + second * first_gradient) / (first_gradient + second_gradient)
+}
+
+// A weight between two directions at x,y, unless the four diagonal neighbours
+// lean further from an even 0.5 than it does, in which case it's their mean
+fn refine(meta: &Metadata, weights: &[f32], x: u32, y: u32) -> f32 {
+	let central = sample(meta, weights, x, y, 0, 0);
+	let neighbourhood = [(-1, -1), (1, -1), (-1, 1), (1, 1)].iter()
+		.map(|&(dx, dy)| sample(meta, weights, x, y, dx, dy))
+		.sum::<f32>() / 4.0;
+
+	if (0.5 - central).abs() < (0.5 - neighbourhood).abs() {
+		neighbourhood
+	} else {
+		central
+	}
+}
+
+#[cfg(test)]
+mod debayer_tests {
+	use super::*;
+	use crate::image::CFA;
+	use crate::test_util::assert_close;
+
+	// An RGB reference with fine rings, hard diagonal stripes, and slow color
+	// changes over it. Something like a detailed photo.
+	fn reference(width: u32, height: u32) -> Vec<f32> {
+		let mut data = Vec::with_capacity((width * height * 3) as usize);
+
+		for y in 0..height {
+			for x in 0..width {
+				let (fx, fy) = (x as f32, y as f32);
+				let radius = (fx - width as f32 / 2.0).powi(2) + (fy - height as f32 / 2.0).powi(2);
+				let stripe = if (fx - fy * 0.7) as i32 % 17 < 4 { 0.15 } else { 0.0 };
+				let light = 0.5 + 0.25 * (radius / 40.0 * 0.6).sin() + stripe;
+
+				data.push(light * (0.8 + 0.2 * fx / width as f32));
+				data.push(light);
+				data.push(light * (0.6 + 0.3 * fy / height as f32));
+			}
+		}
+
+		data
+	}
+
+	// The reference as the sensor would have seen it through `cfa`
+	fn mosaic(reference: &[f32], width: u32, height: u32, cfa: CFA) -> Image<Sensor, f32> {
+		let meta = Metadata::test(width, height, cfa);
+		let data = (0..(width * height) as usize)
+			.map(|i| reference[i * 3 + meta.color_at_index(i) as usize])
+			.collect();
+
+		Image { kind: Sensor {}, data, meta }
+	}
+
+	fn psnr(reference: &[f32], interpolated: &[f32]) -> f32 {
+		let squared: f32 = reference.iter().zip(interpolated.iter()).map(|(a, b)| (a - b).powi(2)).sum();
+		10.0 * (reference.len() as f32 / squared).log10()
+	}
+
+	fn interpolations() -> Vec<fn() -> Interpolation> {
+		vec![
+			|| Interpolation::NearestNeighbor,
+			|| Interpolation::NearestNeighborFixed,
+			|| Interpolation::NearestNeighborSeeded(7),
+			|| Interpolation::Bilinear,
+			|| Interpolation::ThreePass,
+			|| Interpolation::Vng,
+			|| Interpolation::Ahd,
+			|| Interpolation::Malvar,
+			|| Interpolation::Rcd,
+			|| Interpolation::Amaze
+		]
+	}
+
+	#[test]
+	fn bayer_layouts() {
+		let (width, height) = (6, 6);
+
+		for cfa in [CFA::RGGB, CFA::BGGR, CFA::GRBG, CFA::GBRG].iter() {
+			// One flat color comes out the same everywhere, whichever photosite
+			// each color was taken from
+			let flat: Vec<f32> = (0..width * height).flat_map(|_| vec![0.2, 0.5, 0.8]).collect();
+			let interpolations = vec![
+				("nearest neighbor", Interpolation::NearestNeighborFixed),
+				("bilinear", Interpolation::Bilinear)
+			];
+			for (name, interpolation) in interpolations.into_iter() {
+				let img = Debayer::new(mosaic(&flat, width, height, cfa.clone())).interpolate(interpolation);
+				assert!(img.data == flat, "{:?} through {}", cfa, name);
+			}
+
+			// Bilinear is exact on a ramp away from the edges
+			let ramp: Vec<f32> = (0..width * height)
+				.flat_map(|i| {
+					let (x, y) = ((i % width) as f32, (i / width) as f32);
+					vec![0.05 * x, 0.05 * (x + y), 0.05 * y]
+				})
+				.collect();
+			let img = Debayer::new(mosaic(&ramp, width, height, cfa.clone())).interpolate(Interpolation::Bilinear);
+			for y in 1..height - 1 {
+				for x in 1..width - 1 {
+					for &color in [Color::Red, Color::Green, Color::Blue].iter() {
+						let expected = ramp[((y * width + x) * 3) as usize + color as usize];
+						assert!((img.component(x, y, color) - expected).abs() < 1e-6, "{:?} {} at {},{}", cfa, color, x, y);
+					}
+				}
+			}
+		}
+	}
+
+	#[test]
+	fn xtrans_flat() {
+		// The top left 2x2 of the pattern is all green, so the corner has no
+		// red or blue in its 3x3 and needs the 5x5 to find them
+		let xtrans = CFA::test_xtrans();
+		assert!((0..2).all(|y| (0..2).all(|x| xtrans.color_at(x, y) == Color::Green)));
+
+		// Sizes that cut the 6x6 pattern off partway on the right and bottom
+		for &(width, height) in [(6, 6), (13, 8), (9, 17)].iter() {
+			let flat: Vec<f32> = (0..width * height).flat_map(|_| vec![0.2, 0.5, 0.8]).collect();
+			let img = Debayer::new(mosaic(&flat, width, height, xtrans.clone())).interpolate(Interpolation::ThreePass);
+
+			assert_close(&img.data[..], &flat[..], 1e-6);
+		}
+	}
+
+	#[test]
+	fn beats_bilinear() {
+		let (width, height) = (64, 48);
+		let reference = reference(width, height);
+
+		for cfa in [CFA::RGGB, CFA::BGGR, CFA::GRBG, CFA::GBRG].iter() {
+			let quality = |interpolation: Interpolation| {
+				let img = Debayer::new(mosaic(&reference, width, height, cfa.clone())).interpolate(interpolation);
+				psnr(&reference, &img.data)
+			};
+
+			let bilinear = quality(Interpolation::Bilinear);
+			let rcd = quality(Interpolation::Rcd);
+			let amaze = quality(Interpolation::Amaze);
+
+			assert!(rcd > bilinear + 3.0, "RCD {}dB, bilinear {}dB", rcd, bilinear);
+			assert!(amaze > bilinear + 3.0, "AMaZE {}dB, bilinear {}dB", amaze, bilinear);
+		}
+	}
+
+	#[test]
+	fn keeps_photosites() {
+		// Odd sizes so every layout has edges that end on both colors
+		let (width, height) = (7, 5);
+		let reference = reference(width, height);
+
+		for cfa in [CFA::RGGB, CFA::BGGR, CFA::GRBG, CFA::GBRG, CFA::test_xtrans()].iter() {
+			for interpolation in interpolations() {
+				let sensor = mosaic(&reference, width, height, cfa.clone());
+				let photosites = sensor.data.clone();
+				let img = Debayer::new(sensor).interpolate(interpolation());
+
+				for (i, photosite) in photosites.iter().enumerate() {
+					let (x, y) = img.meta.itoxy(i);
+					assert_eq!(img.component(x, y, img.meta.color_at_xy(x, y)), *photosite);
+				}
+				assert!(img.data.iter().all(|value| value.is_finite()));
+			}
+		}
+	}
+
+	// Something someone else might write
+	struct Gray;
+	impl Demosaic for Gray {
+		fn demosaic(&self, sensor: Image<Sensor, f32>) -> Image<Rgb, f32> {
+			let mut img = expand(sensor);
+			for pix in img.pixel_range() {
+				let (x, y) = img.meta.itoxy(pix);
+				let light = img.component(x, y, img.meta.color_at_xy(x, y));
+
+				for &color in [Color::Red, Color::Green, Color::Blue].iter() {
+					img.set_component(pix, color, light);
+				}
+			}
+
+			img
+		}
+	}
+
+	#[test]
+	fn custom_demosaic() {
+		let (width, height) = (8, 6);
+		let reference = reference(width, height);
+		let sensor = || mosaic(&reference, width, height, CFA::GRBG);
+
+		let gray = Debayer::new(sensor()).demosaic(&Gray);
+		for pix in gray.pixel_range() {
+			assert_eq!(gray.data[pix * 3], gray.data[pix * 3 + 1]);
+			assert_eq!(gray.data[pix * 3], gray.data[pix * 3 + 2]);
+		}
+
+		// Trait objects and the enum give the same as the struct itself
+		let algorithms: Vec<Box<dyn Demosaic>> = vec![Box::new(Rcd), Box::new(Interpolation::Rcd)];
+		let expected = Debayer::new(sensor()).demosaic(&Rcd).data;
+		for algorithm in algorithms.iter() {
+			assert_eq!(Debayer::new(sensor()).demosaic(algorithm.as_ref()).data, expected);
+		}
+		assert_eq!(Debayer::new(sensor()).interpolate(Interpolation::Rcd).data, expected);
+	}
+
+	#[test]
+	fn nearest_neighbor_repeats() {
+		let (width, height) = (16, 12);
+		let reference = reference(width, height);
+		let run = |interpolation: Interpolation| {
+			Debayer::new(mosaic(&reference, width, height, CFA::RGGB)).interpolate(interpolation).data
+		};
+
+		assert_eq!(run(Interpolation::NearestNeighborFixed), run(Interpolation::NearestNeighborFixed));
+		assert_eq!(run(Interpolation::NearestNeighborSeeded(1)), run(Interpolation::NearestNeighborSeeded(1)));
+		assert_ne!(run(Interpolation::NearestNeighborSeeded(1)), run(Interpolation::NearestNeighborSeeded(2)));
+	}
+
+	#[test]
+	fn superpixel_halves() {
+		let (width, height) = (9, 7);
+		let reference = reference(width, height);
+		let sensor = mosaic(&reference, width, height, CFA::GBRG);
+		let photosites = sensor.data.clone();
+		let img = Debayer::new(sensor).interpolate(Interpolation::Superpixel);
+
+		assert_eq!((img.meta.width, img.meta.height), (4, 3));
+		assert_eq!(img.data.len(), 4 * 3 * 3);
+
+		// GBRG, so the block at 1,2 starts at 2,4 which is green, then blue,
+		// red, and green
+		let at = |x: u32, y: u32| photosites[(y * width + x) as usize];
+		let pixel = &img.data[(2 * 4 + 1) * 3..(2 * 4 + 1) * 3 + 3];
+		assert_eq!(pixel, &[at(2, 5), (at(2, 4) + at(3, 5)) / 2.0, at(3, 4)][..]);
+
+		// Every block starts on green, and the CFA says so at the new size
+		assert_eq!((img.meta.cfa.width(), img.meta.cfa.height()), (1, 1));
+		assert_eq!(img.meta.color_at_xy(3, 2), Color::Green);
+
+		// X-Trans blocks are 3x3, and start on four different colors
+		let xtrans = CFA::test_xtrans();
+		let sensor = mosaic(&reference, width, height, xtrans.clone());
+		let img = Debayer::new(sensor).interpolate(Interpolation::Superpixel);
+
+		assert_eq!((img.meta.width, img.meta.height), (3, 2));
+		assert_eq!((img.meta.cfa.width(), img.meta.cfa.height()), (2, 2));
+		for (x, y) in [(0, 0), (1, 0), (2, 0), (0, 1), (2, 1)].iter() {
+			assert_eq!(img.meta.color_at_xy(*x, *y), xtrans.color_at(x * 3, y * 3));
+		}
+	}
+
+	#[test]
+	fn strips_match_whole() {
+		// Strips that don't divide the height, and an odd number of rows
+		let (width, height) = (20, 37);
+		let reference = reference(width, height);
+
+		// X-Trans goes through ThreePass whichever is asked for, apart from the
+		// ones that fall back to bilinear
+		for cfa in [CFA::RGGB, CFA::BGGR, CFA::GRBG, CFA::GBRG, CFA::test_xtrans()].iter() {
+			// Random nearest neighbour picks differently in strips, seeded or not
+			let deterministic = interpolations().into_iter().filter(|interpolation| {
+				!matches!(interpolation(), Interpolation::NearestNeighbor | Interpolation::NearestNeighborSeeded(_))
+			});
+
+			for interpolation in deterministic {
+				let whole = Debayer::new(mosaic(&reference, width, height, cfa.clone())).interpolate(interpolation());
+				let strips = Debayer::new(mosaic(&reference, width, height, cfa.clone()))
+					.demosaic_strips(&interpolation(), 5);
+
+				assert_eq!(strips.meta.height, height);
+				assert!(strips.data == whole.data);
+
+				// Streamed in strips thinner than the reach
+				let mut streamed = Vec::new();
+				Debayer::new(mosaic(&reference, width, height, cfa.clone()))
+					.strips(&interpolation(), 3, |_, strip| streamed.extend_from_slice(strip));
+				assert!(streamed == whole.data);
+			}
+		}
+
+		let half = Debayer::new(mosaic(&reference, width, height, CFA::RGGB)).demosaic_strips(&Superpixel, 5);
+		assert_eq!((half.meta.width, half.meta.height), (10, 18));
+	}
+
+	#[test]
+	fn false_color() {
+		// A gray disc on a darker gray, which bilinear fringes with color
+		let (width, height) = (24, 16);
+		let reference: Vec<f32> = (0..width * height)
+			.flat_map(|i| {
+				let (x, y) = ((i % width) as f32 - 11.5, (i / width) as f32 - 7.5);
+				let light = if x * x + y * y < 30.0 { 0.8 } else { 0.2 };
+				vec![light; 3]
+			})
+			.collect();
+
+		let chroma = |img: &Image<Rgb, f32>| {
+			img.data.chunks(3).map(|rgb| (rgb[0] - rgb[1]).abs() + (rgb[2] - rgb[1]).abs()).sum::<f32>()
+		};
+
+		let plain = Debayer::new(mosaic(&reference, width, height, CFA::RGGB))
+			.interpolate(Interpolation::Bilinear);
+		let suppressed = Debayer::new(mosaic(&reference, width, height, CFA::RGGB))
+			.false_color(2)
+			.interpolate(Interpolation::Bilinear);
+
+		assert!(chroma(&suppressed) < chroma(&plain) / 2.0, "{} from {}", chroma(&suppressed), chroma(&plain));
+
+		// Green is left alone
+		for (a, b) in plain.data.chunks(3).zip(suppressed.data.chunks(3)) {
+			assert_eq!(a[1], b[1]);
+		}
+
+		let strips = Debayer::new(mosaic(&reference, width, height, CFA::RGGB))
+			.false_color(2)
+			.demosaic_strips(&Interpolation::Bilinear, 3);
+		assert!(strips.data == suppressed.data);
+	}
+}
@@ -0,0 +1,183 @@
+use super::{bayer_only, bayer_only_interpolate, combine, expand, mirrored, refine, sample, sensor_plane, Demosaic};
+use crate::image::{Color, Image, Metadata, Rgb, Sensor};
+
+// Ratio Corrected Demosaicing, Luis Sanz Rodríguez's algorithm as it's used in
+// RawTherapee and darktable.
+//
+// Green is interpolated vertically and horizontally from the neighbouring
+// greens scaled by how the low passed CFA changes between them, which is the
+// ratio correction, and the two are mixed by how much the image changes in
+// each direction. Red and blue follow from color differences to that green,
+// on the diagonals first and then at the greens.
+
+// Keeps the divisions sane in flat, black areas
+const EPSILON: f32 = 1e-5;
+
+/// See [Interpolation::Rcd](super::Interpolation::Rcd)
+pub struct Rcd;
+
+impl Demosaic for Rcd {
+	fn demosaic(&self, sensor: Image<Sensor, f32>) -> Image<Rgb, f32> {
+		self.demosaic_expanded(expand(sensor))
+	}
+
+	fn demosaic_expanded(&self, img: Image<Rgb, f32>) -> Image<Rgb, f32> {
+		bayer_only_interpolate(img, Self::interpolate)
+	}
+
+	// Green reads four pixels out, red and blue at red and blue two more,
+	// and red and blue at green three past those
+	fn reach(&self, meta: &Metadata) -> Option<u32> {
+		bayer_only(meta, 9)
+	}
+}
+
+impl Rcd {
+	fn interpolate(img: &mut Image<Rgb, f32>) {
+		let cfa = sensor_plane(img);
+		let meta = &img.meta;
+
+		let vertical_horizontal = Self::discrimination(meta, &cfa, (0, 1), (1, 0));
+		let low_pass = Self::low_pass(meta, &cfa);
+
+		// Green at red and blue
+		let mut green = cfa.clone();
+		for (i, out) in green.iter_mut().enumerate() {
+			let (x, y) = meta.itoxy(i);
+			if meta.color_at_xy(x, y) == Color::Green {
+				continue;
+			}
+
+			let estimate = |dx: i32, dy: i32| {
+				let gradient = EPSILON
+					+ (sample(meta, &cfa, x, y, dx, dy) - sample(meta, &cfa, x, y, -dx, -dy)).abs()
+					+ (cfa[i] - sample(meta, &cfa, x, y, 2 * dx, 2 * dy)).abs()
+					+ (sample(meta, &cfa, x, y, dx, dy) - sample(meta, &cfa, x, y, 3 * dx, 3 * dy)).abs()
+					+ (sample(meta, &cfa, x, y, 2 * dx, 2 * dy) - sample(meta, &cfa, x, y, 4 * dx, 4 * dy)).abs();
+
+				let far = sample(meta, &low_pass, x, y, 2 * dx, 2 * dy);
+				let ratio = Self::ratio(low_pass[i], far);
+
+				(sample(meta, &cfa, x, y, dx, dy) * ratio, gradient)
+			};
+
+			let vertical = combine(estimate(0, -1), estimate(0, 1));
+			let horizontal = combine(estimate(-1, 0), estimate(1, 0));
+			let weight = refine(meta, &vertical_horizontal, x, y);
+
+			*out = weight * horizontal + (1.0 - weight) * vertical;
+		}
+
+		// Red at blue and blue at red, from the diagonals
+		let diagonal = Self::discrimination(meta, &cfa, (1, 1), (1, -1));
+		let mut planes = [cfa.clone(), green.clone(), cfa.clone()];
+		for i in 0..cfa.len() {
+			let (x, y) = meta.itoxy(i);
+			let current = meta.color_at_xy(x, y);
+			if current == Color::Green {
+				continue;
+			}
+
+			let color = if current == Color::Red { Color::Blue } else { Color::Red };
+			let estimate = |dx: i32, dy: i32| {
+				let gradient = EPSILON
+					+ (sample(meta, &cfa, x, y, dx, dy) - sample(meta, &cfa, x, y, -dx, -dy)).abs()
+					+ (sample(meta, &cfa, x, y, dx, dy) - sample(meta, &cfa, x, y, 3 * dx, 3 * dy)).abs()
+					+ (green[i] - sample(meta, &green, x, y, 2 * dx, 2 * dy)).abs();
+
+				(sample(meta, &cfa, x, y, dx, dy) - sample(meta, &green, x, y, dx, dy), gradient)
+			};
+
+			let falling = combine(estimate(-1, -1), estimate(1, 1));
+			let rising = combine(estimate(1, -1), estimate(-1, 1));
+			let weight = refine(meta, &diagonal, x, y);
+
+			planes[color as usize][i] = green[i] + weight * rising + (1.0 - weight) * falling;
+		}
+
+		// Red and blue at green, from the red and blue all around it now
+		for i in 0..cfa.len() {
+			let (x, y) = meta.itoxy(i);
+			if meta.color_at_xy(x, y) != Color::Green {
+				continue;
+			}
+
+			let weight = refine(meta, &vertical_horizontal, x, y);
+			for &color in [Color::Red, Color::Blue].iter() {
+				let plane = &planes[color as usize];
+				let estimate = |dx: i32, dy: i32| {
+					let gradient = EPSILON
+						+ (green[i] - sample(meta, &green, x, y, 2 * dx, 2 * dy)).abs()
+						+ (sample(meta, plane, x, y, dx, dy) - sample(meta, plane, x, y, -dx, -dy)).abs()
+						+ (sample(meta, plane, x, y, dx, dy) - sample(meta, plane, x, y, 3 * dx, 3 * dy)).abs();
+
+					(sample(meta, plane, x, y, dx, dy) - sample(meta, &green, x, y, dx, dy), gradient)
+				};
+
+				let vertical = combine(estimate(0, -1), estimate(0, 1));
+				let horizontal = combine(estimate(-1, 0), estimate(1, 0));
+
+				let value = green[i] + weight * horizontal + (1.0 - weight) * vertical;
+				planes[color as usize][i] = value;
+			}
+		}
+
+		for pix in img.pixel_range() {
+			let current = img.meta.color_at_index(pix);
+
+			for &color in [Color::Red, Color::Green, Color::Blue].iter() {
+				if color != current {
+					let value = planes[color as usize][pix];
+					img.set_component(pix, color, value);
+				}
+			}
+		}
+	}
+
+	fn ratio(near: f32, far: f32) -> f32 {
+		let sum = near + far;
+
+		if sum > EPSILON {
+			(2.0 * near / sum).min(4.0)
+		} else {
+			1.0
+		}
+	}
+
+	// For every pixel, how much more the image changes along `first` than
+	// along `second`, from 0.0 to 1.0. It's the energy of the second derivative
+	// along each, over the pixel and the two beside it along the same line,
+	// which covers both colors of the line.
+	fn discrimination(meta: &Metadata, cfa: &[f32], first: (i32, i32), second: (i32, i32)) -> Vec<f32> {
+		let energy = |x: u32, y: u32, (dx, dy): (i32, i32)| {
+			(-1..=1).map(|k| {
+				let (cx, cy) = mirrored(meta, x, y, k * dx, k * dy);
+				let curvature = sample(meta, cfa, cx, cy, -2 * dx, -2 * dy)
+					- 2.0 * sample(meta, cfa, cx, cy, 0, 0)
+					+ sample(meta, cfa, cx, cy, 2 * dx, 2 * dy);
+
+				curvature * curvature
+			}).sum::<f32>()
+		};
+
+		(0..cfa.len()).map(|i| {
+			let (x, y) = meta.itoxy(i);
+			let (along_first, along_second) = (energy(x, y, first), energy(x, y, second));
+
+			(along_first + EPSILON) / (along_first + along_second + 2.0 * EPSILON)
+		}).collect()
+	}
+
+	// A 3x3 low pass of the CFA, which is in proportion to the luminance
+	// around each pixel whichever color it is
+	fn low_pass(meta: &Metadata, cfa: &[f32]) -> Vec<f32> {
+		(0..cfa.len()).map(|i| {
+			let (x, y) = meta.itoxy(i);
+			let at = |dx: i32, dy: i32| sample(meta, cfa, x, y, dx, dy);
+
+			cfa[i]
+				+ 0.5 * (at(0, -1) + at(0, 1) + at(-1, 0) + at(1, 0))
+				+ 0.25 * (at(-1, -1) + at(1, -1) + at(-1, 1) + at(1, 1))
+		}).collect()
+	}
+}
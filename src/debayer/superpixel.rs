@@ -0,0 +1,94 @@
+use super::Demosaic;
+use crate::image::{CFA, Image, Metadata, Rgb, Sensor};
+
+/// See [Interpolation::Superpixel](super::Interpolation::Superpixel)
+pub struct Superpixel;
+
+impl Demosaic for Superpixel {
+	fn demosaic(&self, sensor: Image<Sensor, f32>) -> Image<Rgb, f32> {
+		let size = Self::block_size(&sensor.meta);
+		let width = sensor.meta.width / size;
+		let height = sensor.meta.height / size;
+
+		let mut data = Vec::with_capacity((width * height * 3) as usize);
+		for by in 0..height {
+			for bx in 0..width {
+				let mut sums = [0.0; 3];
+				let mut counts = [0.0; 3];
+
+				for y in by * size..(by + 1) * size {
+					for x in bx * size..(bx + 1) * size {
+						let color = sensor.meta.color_at_xy(x, y) as usize;
+						sums[color] += sensor.data[sensor.meta.xytoi(x, y)];
+						counts[color] += 1.0;
+					}
+				}
+
+				for (sum, count) in sums.iter().zip(counts.iter()) {
+					data.push(if *count > 0.0 { sum / count } else { 0.0 });
+				}
+			}
+		}
+
+		// Each pixel is a whole block now. The CFA is changed to give the color
+		// each block starts at, so it still lines up with the pixels, and
+		// libraw's per photosite black level pattern doesn't line up with
+		// anything any more so it's dropped. The per color levels still hold.
+		let mut meta = sensor.meta;
+		meta.width = width;
+		meta.height = height;
+		meta.cfa = Self::block_cfa(&meta.cfa, size);
+		meta.colordata.cblack[4] = 0;
+		meta.colordata.cblack[5] = 0;
+
+		Image {
+			kind: Rgb {},
+			data,
+			meta
+		}
+	}
+}
+
+impl Superpixel {
+	// The smallest square block size where every block in the image has every
+	// color in it. It's 2 for Bayer and 3 for X-Trans.
+	fn block_size(meta: &Metadata) -> u32 {
+		let cfa = &meta.cfa;
+		let largest = cfa.width().max(cfa.height()).max(2);
+
+		(2..=largest).find(|&size| {
+			(0..cfa.height()).all(|by| (0..cfa.width()).all(|bx| {
+				let mut seen = [false; 3];
+				for y in by * size..(by + 1) * size {
+					for x in bx * size..(bx + 1) * size {
+						seen[cfa.color_at(x, y) as usize] = true;
+					}
+				}
+
+				seen.iter().all(|&seen| seen)
+			}))
+		}).unwrap_or(largest)
+	}
+
+	// The CFA sampled at the start of every block. It repeats once the blocks
+	// come back around to the same place in the tile.
+	fn block_cfa(cfa: &CFA, size: u32) -> CFA {
+		let gcd = |mut a: u32, mut b: u32| {
+			while b != 0 {
+				let r = a % b;
+				a = b;
+				b = r;
+			}
+			a
+		};
+
+		let width = cfa.width() / gcd(cfa.width(), size);
+		let height = cfa.height() / gcd(cfa.height(), size);
+		let pattern = (0..height)
+			.flat_map(|y| (0..width).map(move |x| (x, y)))
+			.map(|(x, y)| cfa.color_at(x * size, y * size))
+			.collect();
+
+		CFA::new(width, height, pattern)
+	}
+}
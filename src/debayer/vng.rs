@@ -0,0 +1,226 @@
+use super::{bayer_only, bayer_only_interpolate, expand, neighbour, window_mean, Demosaic};
+use crate::image::{Color, Image, Metadata, Rgb, Sensor};
+
+// Variable Number of Gradients, from Chang, Cheung and Pang's "Color filter
+// array recovery using a threshold-based variable number of gradients".
+//
+// Gradients are taken in eight directions from differences between photosites
+// of the same color in the 5x5 around a pixel. The directions with the
+// smallest gradients are the ones the image changes least along, and the
+// missing colors come from the color differences averaged along only those.
+
+// A gradient term is the difference between the photosite at x,y, relative to
+// the pixel, and the one `2` steps back against the direction. If `adjacent`
+// is set, and the photosite one step back is the same color, that's used
+// instead.
+struct Term {
+	x: i32,
+	y: i32,
+	weight: f32,
+	adjacent: bool
+}
+
+// Terms for north. The other axis directions are these rotated.
+const AXIS_TERMS: [Term; 6] = [
+	Term { x: 0, y: -1, weight: 1.0, adjacent: false },
+	Term { x: 0, y: -2, weight: 1.0, adjacent: false },
+	Term { x: -1, y: -1, weight: 0.5, adjacent: false },
+	Term { x: 1, y: -1, weight: 0.5, adjacent: false },
+	Term { x: -1, y: -2, weight: 0.5, adjacent: false },
+	Term { x: 1, y: -2, weight: 0.5, adjacent: false }
+];
+
+// Terms for north east. The other diagonals are these rotated.
+const DIAGONAL_TERMS: [Term; 6] = [
+	Term { x: 1, y: -1, weight: 1.0, adjacent: false },
+	Term { x: 2, y: -2, weight: 1.0, adjacent: false },
+	Term { x: 0, y: -1, weight: 0.5, adjacent: true },
+	Term { x: 1, y: 0, weight: 0.5, adjacent: true },
+	Term { x: 1, y: -2, weight: 0.5, adjacent: true },
+	Term { x: 2, y: -1, weight: 0.5, adjacent: true }
+];
+
+// Directions with a gradient up to `minimum * THRESHOLD_MIN + (maximum -
+// minimum) * THRESHOLD_RANGE` are used. These are the values from the paper.
+const THRESHOLD_MIN: f32 = 1.5;
+const THRESHOLD_RANGE: f32 = 0.5;
+
+struct Direction {
+	dx: i32,
+	dy: i32,
+	diagonal: bool,
+	// Quarter turns from north, or north east, to get here
+	turns: usize
+}
+
+impl Direction {
+	// The eight directions, going clockwise from north
+	fn all() -> Vec<Direction> {
+		let mut directions = vec![];
+		let (mut axis, mut diagonal) = ((0, -1), (1, -1));
+
+		for turns in 0..4 {
+			directions.push(Direction { dx: axis.0, dy: axis.1, diagonal: false, turns });
+			directions.push(Direction { dx: diagonal.0, dy: diagonal.1, diagonal: true, turns });
+
+			axis = rotate(axis);
+			diagonal = rotate(diagonal);
+		}
+
+		directions
+	}
+}
+
+// A quarter turn clockwise, with y going down
+fn rotate((x, y): (i32, i32)) -> (i32, i32) {
+	(-y, x)
+}
+
+/// See [Interpolation::Vng](super::Interpolation::Vng)
+pub struct Vng;
+
+impl Demosaic for Vng {
+	fn demosaic(&self, sensor: Image<Sensor, f32>) -> Image<Rgb, f32> {
+		self.demosaic_expanded(expand(sensor))
+	}
+
+	fn demosaic_expanded(&self, img: Image<Rgb, f32>) -> Image<Rgb, f32> {
+		bayer_only_interpolate(img, Self::interpolate)
+	}
+
+	fn reach(&self, meta: &Metadata) -> Option<u32> {
+		bayer_only(meta, 2)
+	}
+}
+
+impl Vng {
+	fn interpolate(img: &mut Image<Rgb, f32>) {
+		let directions = Direction::all();
+
+		for pix in img.pixel_range() {
+			let (x, y) = img.meta.itoxy(pix);
+			let current = img.meta.color_at_xy(x, y);
+
+			let gradients: Vec<Option<f32>> = directions.iter()
+				.map(|direction| Self::gradient(img, x, y, direction))
+				.collect();
+
+			let minimum = gradients.iter().flatten().copied().fold(f32::INFINITY, f32::min);
+			let maximum = gradients.iter().flatten().copied().fold(0.0, f32::max);
+			let threshold = minimum * THRESHOLD_MIN + (maximum - minimum) * THRESHOLD_RANGE;
+
+			let mut sums = [0.0; 3];
+			let mut count = 0;
+			for (direction, gradient) in directions.iter().zip(gradients.iter()) {
+				match gradient {
+					Some(gradient) if *gradient <= threshold => (),
+					_ => continue
+				}
+
+				if let Some(means) = Self::region_means(img, x, y, direction) {
+					for (sum, mean) in sums.iter_mut().zip(means.iter()) {
+						*sum += mean;
+					}
+					count += 1;
+				}
+			}
+
+			for &color in [Color::Red, Color::Green, Color::Blue].iter() {
+				if color == current {
+					continue;
+				}
+
+				let value = if count > 0 {
+					let difference = (sums[color as usize] - sums[current as usize]) / count as f32;
+					Some(img.component(x, y, current) + difference)
+				} else {
+					// Only happens in images too small to have any gradients
+					window_mean(img, x, y, 1, color, |value, _| value)
+						.or_else(|| window_mean(img, x, y, 2, color, |value, _| value))
+				};
+
+				if let Some(value) = value {
+					img.set_component(pix, color, value);
+				}
+			}
+		}
+	}
+
+	// The weighted mean of the terms in `direction` that are inside the image.
+	// None if none of them are.
+	fn gradient(img: &Image<Rgb, f32>, x: u32, y: u32, direction: &Direction) -> Option<f32> {
+		let terms = if direction.diagonal { &DIAGONAL_TERMS } else { &AXIS_TERMS };
+
+		let mut sum = 0.0;
+		let mut weights = 0.0;
+		for term in terms.iter() {
+			let mut offset = (term.x, term.y);
+			for _ in 0..direction.turns {
+				offset = rotate(offset);
+			}
+
+			let first = match neighbour(&img.meta, x, y, offset.0, offset.1) {
+				Some(first) => first,
+				None => continue
+			};
+			let color = img.meta.color_at_xy(first.0, first.1);
+
+			let adjacent = neighbour(&img.meta, x, y, offset.0 - direction.dx, offset.1 - direction.dy)
+				.filter(|&(nx, ny)| term.adjacent && img.meta.color_at_xy(nx, ny) == color);
+			let second = adjacent.or_else(|| {
+				neighbour(&img.meta, x, y, offset.0 - 2 * direction.dx, offset.1 - 2 * direction.dy)
+			});
+
+			if let Some((nx, ny)) = second {
+				let difference = img.component(first.0, first.1, color) - img.component(nx, ny, color);
+				sum += term.weight * difference.abs();
+				weights += term.weight;
+			}
+		}
+
+		if weights > 0.0 {
+			Some(sum / weights)
+		} else {
+			None
+		}
+	}
+
+	// The mean of each color in the area `direction` points to. Colors are
+	// taken from the line out from the pixel where they're on it, or from
+	// beside it where they're not. None if a color isn't in the area at all.
+	fn region_means(img: &Image<Rgb, f32>, x: u32, y: u32, direction: &Direction) -> Option<[f32; 3]> {
+		let (dx, dy) = (direction.dx, direction.dy);
+		let line = [(0, 0), (dx, dy), (2 * dx, 2 * dy)];
+
+		let beside: Vec<(i32, i32)> = if direction.diagonal {
+			vec![(dx + 1, dy), (dx - 1, dy), (dx, dy + 1), (dx, dy - 1)]
+		} else {
+			let (px, py) = rotate((dx, dy));
+			(0..3).flat_map(|step| vec![
+				(step * dx + px, step * dy + py),
+				(step * dx - px, step * dy - py)
+			]).collect()
+		};
+
+		let mean = |offsets: &[(i32, i32)], color: Color| {
+			let values: Vec<f32> = offsets.iter()
+				.filter_map(|&(ox, oy)| neighbour(&img.meta, x, y, ox, oy))
+				.filter(|&(nx, ny)| img.meta.color_at_xy(nx, ny) == color)
+				.map(|(nx, ny)| img.component(nx, ny, color))
+				.collect();
+
+			if values.is_empty() {
+				None
+			} else {
+				Some(values.iter().sum::<f32>() / values.len() as f32)
+			}
+		};
+
+		let mut means = [0.0; 3];
+		for &color in [Color::Red, Color::Green, Color::Blue].iter() {
+			means[color as usize] = mean(&line, color).or_else(|| mean(&beside, color))?;
+		}
+
+		Some(means)
+	}
+}
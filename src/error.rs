@@ -0,0 +1,59 @@
+use std::fmt;
+use std::io;
+
+/// Everything that can go wrong while reading a raw file
+#[derive(Debug)]
+pub enum Error {
+	/// The file or reader could not be read from
+	Io(io::Error),
+	/// The file decoded, but doesn't contain mosaiced sensor data we can work with
+	UnsupportedFormat,
+	/// libraw failed to decode the data
+	Decode(libraw::Error),
+	/// The decoded sizes don't agree with the amount of data we got
+	BadDimensions {
+		width: u32,
+		height: u32,
+		len: usize
+	},
+	/// The color filter array described by the decoder isn't one we support
+	UnsupportedCfa(String)
+}
+
+impl fmt::Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Error::Io(err) => write!(f, "failed to read raw data: {}", err),
+			Error::UnsupportedFormat => write!(f, "the file does not contain supported raw sensor data"),
+			Error::Decode(err) => write!(f, "failed to decode raw data: {}", err),
+			Error::BadDimensions { width, height, len } => write!(
+				f,
+				"raw data is {}x{} but {} values were decoded",
+				width, height, len
+			),
+			Error::UnsupportedCfa(desc) => write!(f, "unsupported color filter array: {}", desc)
+		}
+	}
+}
+
+impl std::error::Error for Error {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		match self {
+			Error::Io(err) => Some(err),
+			Error::Decode(err) => Some(err),
+			_ => None
+		}
+	}
+}
+
+impl From<io::Error> for Error {
+	fn from(err: io::Error) -> Self {
+		Error::Io(err)
+	}
+}
+
+impl From<libraw::Error> for Error {
+	fn from(err: libraw::Error) -> Self {
+		Error::Decode(err)
+	}
+}
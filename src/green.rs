@@ -0,0 +1,48 @@
+use crate::debayer::mirrored;
+use crate::image::{Color, Image, Sensor};
+
+// The two greens of a Bayer CFA, the one in the red row and the one in the
+// blue row, are each other's diagonal neighbours. Every green is compared to
+// those four and to the closest greens of its own kind, which all sit two
+// photosites away, and where they differ by less than the threshold it's
+// moved halfway towards the other kind. Doing it to both kinds meets in the
+// middle. Both sets are centered on the photosite so gradients don't count as
+// imbalance, and differences over the threshold are taken to be detail.
+
+// The other kind of green
+const OTHER: [(i32, i32); 4] = [(-1, -1), (1, -1), (-1, 1), (1, 1)];
+// The same kind of green, including the photosite itself
+const SAME: [(i32, i32); 5] = [(0, 0), (0, -2), (-2, 0), (2, 0), (0, 2)];
+
+pub(crate) fn equilibrate(rimg: &mut Image<Sensor, f32>, threshold: f32) {
+	// The greens have to be on a diagonal, and they're the only color twice
+	let cfa = &rimg.meta.cfa;
+	let diagonal = cfa.color_at(0, 0) == cfa.color_at(1, 1) || cfa.color_at(1, 0) == cfa.color_at(0, 1);
+	if !cfa.is_bayer() || !diagonal {
+		return;
+	}
+
+	let source = rimg.data.clone();
+	let meta = &rimg.meta;
+
+	for (i, light) in rimg.data.iter_mut().enumerate() {
+		let (x, y) = meta.itoxy(i);
+		if meta.color_at_xy(x, y) != Color::Green {
+			continue;
+		}
+
+		let mean = |offsets: &[(i32, i32)]| {
+			offsets.iter().map(|&(dx, dy)| {
+				// Reflected at the edges, which keeps the CFA color
+				let (nx, ny) = mirrored(meta, x, y, dx, dy);
+				source[meta.xytoi(nx, ny)]
+			}).sum::<f32>() / offsets.len() as f32
+		};
+		let (same, other) = (mean(&SAME), mean(&OTHER));
+
+		let level = (same + other) / 2.0;
+		if level > 0.0 && (other - same).abs() / level < threshold {
+			*light += (other - same) / 2.0;
+		}
+	}
+}
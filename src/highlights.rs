@@ -0,0 +1,290 @@
+use crate::image::{Image, Metadata, Sensor};
+
+/// What to do with photosites that saturated. It's meant to run on white
+/// balanced sensor data, before it's demosaiced, and uses the clip levels in
+/// the metadata to know where each color saturated.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Highlights {
+	/// Clip every color where the first one clips, so highlights go to white
+	/// instead of magenta or cyan. Loses the most detail.
+	Clip,
+	/// Blend clipped areas towards their brightest value, by how much of the
+	/// area is clipped. Highlights fade to white without a hard edge and keep
+	/// their brightness above the clip levels.
+	Blend,
+	/// Rebuild clipped colors from the unclipped ones using the color ratios
+	/// of the closest unclipped area. Where every color is clipped they're all
+	/// set to the brightest.
+	Reconstruct
+}
+
+// How far to look, in CFA tiles, for an unclipped area to take ratios from
+const RATIO_SEARCH: i64 = 6;
+
+// Photosites this close under the clip level count as clipped. They're never
+// quite at it after scaling and white balance.
+const CLIP_TOLERANCE: f32 = 0.995;
+
+pub(crate) fn clip(rimg: &mut Image<Sensor, f32>) {
+	let level = rimg.meta.clip_levels.iter().copied().fold(f32::INFINITY, f32::min);
+
+	for light in rimg.data.iter_mut() {
+		*light = light.min(level);
+	}
+
+	rimg.meta.clip_levels = [level; 3];
+}
+
+// The photosites in the 3x3 around each one that's near something clipped
+// are blended towards the brightest of them, by how many of them are clipped.
+pub(crate) fn blend(rimg: &mut Image<Sensor, f32>) {
+	let clipped = clipped_map(rimg);
+	let source = rimg.data.clone();
+	let meta = &rimg.meta;
+
+	for (i, light) in rimg.data.iter_mut().enumerate() {
+		let (x, y) = meta.itoxy(i);
+
+		let mut count = 0;
+		let mut clipped_count = 0;
+		let mut brightest = 0f32;
+		for (nx, ny) in window(meta.width, meta.height, x, y, 1) {
+			let ni = meta.xytoi(nx, ny);
+
+			count += 1;
+			brightest = brightest.max(source[ni]);
+			if clipped[ni] {
+				clipped_count += 1;
+			}
+		}
+
+		if clipped_count > 0 {
+			let amount = clipped_count as f32 / count as f32;
+			*light += (brightest - *light) * amount;
+		}
+	}
+}
+
+// Works on CFA tiles. Each clipped color of a tile is estimated from every
+// unclipped color in it times the ratio between the two, taken from the
+// closest tiles where neither is clipped. The tiles at the right and bottom
+// are cut short when the image isn't a whole number of them.
+pub(crate) fn reconstruct(rimg: &mut Image<Sensor, f32>) {
+	let clipped = clipped_map(rimg);
+	let (tile_width, tile_height) = (rimg.meta.cfa.width(), rimg.meta.cfa.height());
+	let columns = rimg.meta.width.div_ceil(tile_width) as i64;
+	let rows = rimg.meta.height.div_ceil(tile_height) as i64;
+
+	// The mean of each color in every tile and whether any of it was clipped.
+	// A color a cut short tile doesn't have counts as clipped, so it's never
+	// rebuilt from, and the tile's only worked on if a photosite it does have
+	// was clipped.
+	let mut tiles: Vec<([f32; 3], [bool; 3])> = Vec::with_capacity((columns * rows) as usize);
+	let mut any_clipped = Vec::with_capacity((columns * rows) as usize);
+	for row in 0..rows as u32 {
+		for col in 0..columns as u32 {
+			let mut sums = [0.0; 3];
+			let mut counts = [0.0; 3];
+			let mut tile_clipped = [false; 3];
+
+			for (x, y) in tile(&rimg.meta, col, row) {
+				let i = rimg.meta.xytoi(x, y);
+				let color = rimg.meta.color_at_xy(x, y) as usize;
+
+				sums[color] += rimg.data[i];
+				counts[color] += 1.0;
+				tile_clipped[color] |= clipped[i];
+			}
+
+			any_clipped.push(tile_clipped.iter().any(|&c| c));
+			for (color_clipped, count) in tile_clipped.iter_mut().zip(counts.iter()) {
+				*color_clipped |= *count == 0.0;
+			}
+
+			let mean = |color: usize| sums[color] / f32::max(counts[color], 1.0);
+			tiles.push(([mean(0), mean(1), mean(2)], tile_clipped));
+		}
+	}
+
+	for row in 0..rows {
+		for col in 0..columns {
+			let (means, tile_clipped) = tiles[(row * columns + col) as usize];
+			if !any_clipped[(row * columns + col) as usize] {
+				continue;
+			}
+
+			// Where every color is clipped there's nothing to rebuild from, so
+			// it's made neutral at the brightest of them
+			if tile_clipped.iter().all(|&c| c) {
+				let brightest = means.iter().copied().fold(0.0, f32::max);
+
+				for (x, y) in tile(&rimg.meta, col as u32, row as u32) {
+					let i = rimg.meta.xytoi(x, y);
+					rimg.data[i] = rimg.data[i].max(brightest);
+				}
+
+				continue;
+			}
+
+			for clipped_color in 0..3 {
+				if !tile_clipped[clipped_color] {
+					continue;
+				}
+
+				let estimate = nearest_ratio(&tiles, columns, rows, col, row, clipped_color, tile_clipped)
+					.map(|ratios| {
+						let estimates: Vec<f32> = (0..3)
+							.filter(|&color| !tile_clipped[color] && ratios[color] > 0.0)
+							.map(|color| means[color] * ratios[color])
+							.collect();
+
+						estimates.iter().sum::<f32>() / estimates.len().max(1) as f32
+					});
+
+				// Saturated photosites only tell us the light was at least this bright
+				let estimate = match estimate {
+					Some(estimate) => estimate.max(rimg.meta.clip_levels[clipped_color]),
+					None => continue
+				};
+
+				for (x, y) in tile(&rimg.meta, col as u32, row as u32) {
+					let i = rimg.meta.xytoi(x, y);
+
+					if clipped[i] && rimg.meta.color_at_xy(x, y) as usize == clipped_color {
+						rimg.data[i] = estimate;
+					}
+				}
+			}
+		}
+	}
+}
+
+// The ratio of `color` to each color that isn't in `tile_clipped`, from the
+// tiles closest to col,row where none of those or `color` are clipped
+fn nearest_ratio(tiles: &[([f32; 3], [bool; 3])], columns: i64, rows: i64, col: i64, row: i64, color: usize, tile_clipped: [bool; 3]) -> Option<[f32; 3]> {
+	for radius in 1..=RATIO_SEARCH {
+		let mut sums = [0.0; 3];
+		let mut count = 0.0;
+
+		for ny in (row - radius).max(0)..=(row + radius).min(rows - 1) {
+			for nx in (col - radius).max(0)..=(col + radius).min(columns - 1) {
+				// Only the ring at this radius, the inside was already searched
+				if (ny - row).abs() != radius && (nx - col).abs() != radius {
+					continue;
+				}
+
+				let (means, clipped) = tiles[(ny * columns + nx) as usize];
+				let usable = !clipped[color]
+					&& (0..3).all(|other| tile_clipped[other] || (!clipped[other] && means[other] > 0.0));
+
+				if usable {
+					for other in 0..3 {
+						if !tile_clipped[other] {
+							sums[other] += means[color] / means[other];
+						}
+					}
+					count += 1.0;
+				}
+			}
+		}
+
+		if count > 0.0 {
+			return Some([sums[0] / count, sums[1] / count, sums[2] / count]);
+		}
+	}
+
+	None
+}
+
+fn clipped_map(rimg: &Image<Sensor, f32>) -> Vec<bool> {
+	rimg.data.iter().enumerate().map(|(i, &light)| {
+		let color = rimg.meta.color_at_index(i) as usize;
+		light >= rimg.meta.clip_levels[color] * CLIP_TOLERANCE
+	}).collect()
+}
+
+// Coordinates in the square of `radius` around x,y that are inside the image
+fn window(width: u32, height: u32, x: u32, y: u32, radius: u32) -> impl Iterator<Item = (u32, u32)> {
+	let xs = x.saturating_sub(radius)..(x + radius + 1).min(width);
+	let ys = y.saturating_sub(radius)..(y + radius + 1).min(height);
+
+	ys.flat_map(move |ny| xs.clone().map(move |nx| (nx, ny)))
+}
+
+// Coordinates of the CFA tile at col,row that are inside the image
+fn tile(meta: &Metadata, col: u32, row: u32) -> impl Iterator<Item = (u32, u32)> {
+	let (tile_width, tile_height) = (meta.cfa.width(), meta.cfa.height());
+	let xs = col * tile_width..((col + 1) * tile_width).min(meta.width);
+	let ys = row * tile_height..((row + 1) * tile_height).min(meta.height);
+
+	ys.flat_map(move |y| xs.clone().map(move |x| (x, y)))
+}
+
+#[cfg(test)]
+mod highlights_tests {
+	use super::*;
+	use crate::image::{CFA, Color};
+
+	fn sensor(width: u32, height: u32, cfa: CFA, f: impl Fn(u32, u32, Color) -> f32) -> Image<Sensor, f32> {
+		let mut meta = Metadata::test(width, height, cfa);
+		meta.clip_levels = [1.0; 3];
+
+		let data = (0..(width * height) as usize).map(|i| {
+			let (x, y) = meta.itoxy(i);
+			f(x, y, meta.color_at_xy(x, y)).min(1.0)
+		}).collect();
+
+		Image { kind: Sensor, data, meta }
+	}
+
+	#[test]
+	fn clip_to_lowest() {
+		let mut rimg = sensor(4, 4, CFA::RGGB, |_, _, _| 0.9);
+		rimg.meta.clip_levels = [1.0, 0.8, 1.2];
+		clip(&mut rimg);
+
+		assert!(rimg.data.iter().all(|&light| light == 0.8));
+		assert_eq!(rimg.meta.clip_levels, [0.8; 3]);
+	}
+
+	#[test]
+	fn blend_around_clipped() {
+		let mut rimg = sensor(7, 7, CFA::RGGB, |x, y, _| if (x, y) == (3, 3) { 1.0 } else { 0.5 });
+		blend(&mut rimg);
+
+		// The 3x3 around the clipped photosite moves towards it, and nothing
+		// further out
+		for (i, &light) in rimg.data.iter().enumerate() {
+			let (x, y) = rimg.meta.itoxy(i);
+			let distance = (x as i32 - 3).abs().max((y as i32 - 3).abs());
+
+			match distance {
+				0 => assert_eq!(light, 1.0),
+				1 => assert!(light > 0.5 && light < 1.0, "{} at {},{}", light, x, y),
+				_ => assert_eq!(light, 0.5)
+			}
+		}
+	}
+
+	#[test]
+	fn reconstruct_partial_tiles() {
+		let xtrans = CFA::test_xtrans();
+
+		// A yellowish scene that's brighter past the first 6x6 tile, enough to
+		// clip green there but not red or blue. The image isn't a whole number
+		// of tiles either way.
+		let scene = |x: u32, y: u32, color: Color| {
+			let light = if x >= 6 || y >= 6 { 1.5 } else { 1.0 };
+			light * [0.6, 0.9, 0.3][color as usize]
+		};
+
+		let mut rimg = sensor(9, 8, xtrans, scene);
+		reconstruct(&mut rimg);
+
+		for (i, &light) in rimg.data.iter().enumerate() {
+			let (x, y) = rimg.meta.itoxy(i);
+			let expected = scene(x, y, rimg.meta.color_at_xy(x, y));
+			assert!((light - expected).abs() < 1e-4, "{} at {},{} should be {}", light, x, y, expected);
+		}
+	}
+}
@@ -1,34 +1,228 @@
 use crate::image::Color;
+use std::borrow::Cow;
 
-pub enum CFA {
+/// A color filter array described by a repeating tile of colors. The phase
+/// is where the image starts in the tile, so cropping on an odd row or column
+/// only has to shift it instead of needing a whole new pattern.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CFA {
+	width: u32,
+	height: u32,
+	// Row major, `width * height` long
+	pattern: Cow<'static, [Color]>,
+	x_phase: u32,
+	y_phase: u32
+}
+
+impl CFA {
 	/*
 	R G R G
 	G B G B
 	R G R G
 	G B G B
 	*/
-	RGGB
-}
+	pub const RGGB: CFA = CFA::bayer(&[Color::Red, Color::Green, Color::Green, Color::Blue]);
+
+	/*
+	B G B G
+	G R G R
+	B G B G
+	G R G R
+	*/
+	pub const BGGR: CFA = CFA::bayer(&[Color::Blue, Color::Green, Color::Green, Color::Red]);
+
+	/*
+	G R G R
+	B G B G
+	G R G R
+	B G B G
+	*/
+	pub const GRBG: CFA = CFA::bayer(&[Color::Green, Color::Red, Color::Blue, Color::Green]);
+
+	/*
+	G B G B
+	R G R G
+	G B G B
+	R G R G
+	*/
+	pub const GBRG: CFA = CFA::bayer(&[Color::Green, Color::Blue, Color::Red, Color::Green]);
+
+	const fn bayer(pattern: &'static [Color; 4]) -> CFA {
+		CFA {
+			width: 2,
+			height: 2,
+			pattern: Cow::Borrowed(pattern),
+			x_phase: 0,
+			y_phase: 0
+		}
+	}
+
+	/// A CFA made of a `width` by `height` tile, given row by row. A quad-Bayer
+	/// sensor, for example, is a 4x4 tile where each color covers 2x2 photosites.
+	///
+	/// Panics if the pattern isn't `width * height` long or either is zero.
+	pub fn new(width: u32, height: u32, pattern: Vec<Color>) -> CFA {
+		assert!(width > 0 && height > 0, "CFA tile can't be empty");
+		assert_eq!(
+			pattern.len(),
+			(width * height) as usize,
+			"CFA pattern doesn't match the {}x{} tile", width, height
+		);
+
+		CFA {
+			width,
+			height,
+			pattern: Cow::Owned(pattern),
+			x_phase: 0,
+			y_phase: 0
+		}
+	}
+
+	/// Fujifilm's 6x6 X-Trans pattern, indexed `[y][x]`, for example:
+	/// ```text
+	/// G G R G G B
+	/// G G B G G R
+	/// B R G R B G
+	/// G G B G G R
+	/// G G R G G B
+	/// R B G B R G
+	/// ```
+	pub fn xtrans(pattern: [[Color; 6]; 6]) -> CFA {
+		CFA::new(6, 6, pattern.iter().flat_map(|row| row.iter().copied()).collect())
+	}
 
-impl CFA {
 	pub fn color_at(&self, x: u32, y: u32) -> Color {
-		match self {
-			CFA::RGGB => {
-				if x%2 == 0 {
-					if y%2 == 0 {
-						return Color::Red;
-					} else {
-						return Color::Green;
-					}
-				} else {
-					if y%2 == 0 {
-						return Color::Green;
-					} else {
-						return Color::Blue;
-					}
-				}
+		let tx = (x % self.width + self.x_phase) % self.width;
+		let ty = (y % self.height + self.y_phase) % self.height;
+
+		self.pattern[(ty * self.width + tx) as usize]
+	}
+
+	// Like color_at, but for coordinates that may be outside the image
+	fn color_at_signed(&self, x: i64, y: i64) -> Color {
+		self.color_at(
+			x.rem_euclid(self.width as i64) as u32,
+			y.rem_euclid(self.height as i64) as u32
+		)
+	}
+
+	/// Width of the repeating tile
+	pub fn width(&self) -> u32 {
+		self.width
+	}
+
+	/// Height of the repeating tile
+	pub fn height(&self) -> u32 {
+		self.height
+	}
+
+	/// Whether this is a 2x2 tile of one red, two green, and one blue
+	pub fn is_bayer(&self) -> bool {
+		let count = |color| self.pattern.iter().filter(|&&c| c == color).count();
+
+		self.width == 2 && self.height == 2
+			&& count(Color::Red) == 1
+			&& count(Color::Green) == 2
+			&& count(Color::Blue) == 1
+	}
+
+	/// The CFA of an area that starts at x,y in an image with this CFA
+	pub fn offset(&self, x: u32, y: u32) -> CFA {
+		CFA {
+			x_phase: (self.x_phase + x % self.width) % self.width,
+			y_phase: (self.y_phase + y % self.height) % self.height,
+			..self.clone()
+		}
+	}
+
+	/// The CFA after turning an image, `width` by `height`, upside down
+	pub fn rotate_180(&self, width: u32, height: u32) -> CFA {
+		self.remap(self.width, self.height, |x, y| (width as i64 - 1 - x, height as i64 - 1 - y))
+	}
+
+	/// The CFA after rotating an image that's `height` tall 90 degrees clockwise
+	pub fn rotate_cw(&self, height: u32) -> CFA {
+		self.remap(self.height, self.width, |x, y| (y, height as i64 - 1 - x))
+	}
+
+	/// The CFA after rotating an image that's `width` wide 90 degrees counter-clockwise
+	pub fn rotate_ccw(&self, width: u32) -> CFA {
+		self.remap(self.height, self.width, |x, y| (width as i64 - 1 - y, x))
+	}
+
+	// Build a new tile where every position is the color of this CFA at the
+	// position `source` gives for it
+	fn remap<F>(&self, width: u32, height: u32, source: F) -> CFA
+	where F: Fn(i64, i64) -> (i64, i64)
+	{
+		let mut pattern = Vec::with_capacity((width * height) as usize);
+		for y in 0..height as i64 {
+			for x in 0..width as i64 {
+				let (sx, sy) = source(x, y);
+				pattern.push(self.color_at_signed(sx, sy));
 			}
 		}
+
+		CFA::new(width, height, pattern)
+	}
+
+	/// Map libraw's packed `filters` description to a CFA. libraw stores two
+	/// bits per photosite for a 2x8 tile, with 0 being red, 1 and 3 green, and 2
+	/// blue. The tile is shrunk to 2x2 or 2x4 if the rows repeat.
+	pub(crate) fn from_filters(filters: u32) -> CFA {
+		// The FC macro from libraw
+		let fc = |row: u32, col: u32| (filters >> ((((row << 1) & 14) | (col & 1)) << 1)) & 3;
+		let color = |index: u32| match index {
+			0 => Color::Red,
+			2 => Color::Blue,
+			_ => Color::Green
+		};
+
+		let height = [2, 4, 8].iter().copied().find(|&height| {
+			(height..8).all(|row| (0..2).all(|col| fc(row, col) == fc(row % height, col)))
+		}).unwrap_or(8);
+
+		let pattern = (0..height)
+			.flat_map(|row| (0..2).map(move |col| (row, col)))
+			.map(|(row, col)| color(fc(row, col)))
+			.collect();
+
+		CFA::new(2, height, pattern)
+	}
+
+	/// Build an X-Trans CFA from libraw's `xtrans` table, which uses the same
+	/// 0 red, 1 green, 2 blue numbering as `filters`. Returns None if there's
+	/// any other color in it.
+	pub(crate) fn from_xtrans(xtrans: &[[i8; 6]; 6]) -> Option<CFA> {
+		let mut pattern = [[Color::Green; 6]; 6];
+
+		for (y, row) in xtrans.iter().enumerate() {
+			for (x, index) in row.iter().enumerate() {
+				pattern[y][x] = match index {
+					0 => Color::Red,
+					1 | 3 => Color::Green,
+					2 => Color::Blue,
+					_ => return None
+				};
+			}
+		}
+
+		Some(CFA::xtrans(pattern))
+	}
+}
+
+#[cfg(test)]
+impl CFA {
+	/// A 6x6 X-Trans pattern, for tests
+	pub(crate) fn test_xtrans() -> CFA {
+		CFA::from_xtrans(&[
+			[1, 1, 0, 1, 1, 2],
+			[1, 1, 2, 1, 1, 0],
+			[2, 0, 1, 0, 2, 1],
+			[1, 1, 2, 1, 1, 0],
+			[1, 1, 0, 1, 1, 2],
+			[0, 2, 1, 2, 0, 1]
+		]).unwrap()
 	}
 }
 
@@ -50,4 +244,120 @@ mod cfa_tets {
 		assert_eq!(CFA::RGGB.color_at(2, 3), Color::Green);
 		assert_eq!(CFA::RGGB.color_at(3, 3), Color::Blue);
 	}
-}
\ No newline at end of file
+
+	#[test]
+	fn color_at_bggr() {
+		assert_eq!(CFA::BGGR.color_at(0, 0), Color::Blue);
+		assert_eq!(CFA::BGGR.color_at(1, 0), Color::Green);
+		assert_eq!(CFA::BGGR.color_at(0, 1), Color::Green);
+		assert_eq!(CFA::BGGR.color_at(1, 1), Color::Red);
+
+		assert_eq!(CFA::BGGR.color_at(2, 2), Color::Blue);
+		assert_eq!(CFA::BGGR.color_at(3, 3), Color::Red);
+	}
+
+	#[test]
+	fn color_at_grbg() {
+		assert_eq!(CFA::GRBG.color_at(0, 0), Color::Green);
+		assert_eq!(CFA::GRBG.color_at(1, 0), Color::Red);
+		assert_eq!(CFA::GRBG.color_at(0, 1), Color::Blue);
+		assert_eq!(CFA::GRBG.color_at(1, 1), Color::Green);
+
+		assert_eq!(CFA::GRBG.color_at(3, 2), Color::Red);
+		assert_eq!(CFA::GRBG.color_at(2, 3), Color::Blue);
+	}
+
+	#[test]
+	fn color_at_gbrg() {
+		assert_eq!(CFA::GBRG.color_at(0, 0), Color::Green);
+		assert_eq!(CFA::GBRG.color_at(1, 0), Color::Blue);
+		assert_eq!(CFA::GBRG.color_at(0, 1), Color::Red);
+		assert_eq!(CFA::GBRG.color_at(1, 1), Color::Green);
+
+		assert_eq!(CFA::GBRG.color_at(3, 2), Color::Blue);
+		assert_eq!(CFA::GBRG.color_at(2, 3), Color::Red);
+	}
+
+	#[test]
+	fn from_filters() {
+		assert_eq!(CFA::from_filters(0x94949494), CFA::RGGB);
+		assert_eq!(CFA::from_filters(0x16161616), CFA::BGGR);
+		assert_eq!(CFA::from_filters(0x61616161), CFA::GRBG);
+		assert_eq!(CFA::from_filters(0x49494949), CFA::GBRG);
+
+		// Rows that don't repeat every two keep the full 2x8 tile
+		let tall = CFA::from_filters(0x94949416);
+		assert_eq!(tall.height(), 8);
+		assert_eq!(tall.color_at(0, 0), Color::Blue);
+		assert_eq!(tall.color_at(0, 2), Color::Red);
+		assert_eq!(tall.color_at(0, 8), Color::Blue);
+	}
+
+	#[test]
+	fn color_at_xtrans() {
+		let xtrans = CFA::test_xtrans();
+
+		assert_eq!(xtrans.color_at(0, 0), Color::Green);
+		assert_eq!(xtrans.color_at(2, 0), Color::Red);
+		assert_eq!(xtrans.color_at(5, 0), Color::Blue);
+		assert_eq!(xtrans.color_at(0, 5), Color::Red);
+		assert_eq!(xtrans.color_at(1, 2), Color::Red);
+
+		// Repeats every six
+		assert_eq!(xtrans.color_at(8, 0), Color::Red);
+		assert_eq!(xtrans.color_at(6, 11), Color::Red);
+		assert_eq!(xtrans.color_at(7, 8), Color::Red);
+	}
+
+	#[test]
+	fn color_at_quad_bayer() {
+		use Color::*;
+
+		let quad = CFA::new(4, 4, vec![
+			Red, Red, Green, Green,
+			Red, Red, Green, Green,
+			Green, Green, Blue, Blue,
+			Green, Green, Blue, Blue
+		]);
+
+		assert_eq!(quad.color_at(1, 1), Red);
+		assert_eq!(quad.color_at(2, 1), Green);
+		assert_eq!(quad.color_at(3, 3), Blue);
+		assert_eq!(quad.color_at(5, 4), Red);
+		assert!(!quad.is_bayer());
+		assert!(CFA::GBRG.is_bayer());
+	}
+
+	fn same(a: &CFA, b: &CFA) -> bool {
+		(0..12).all(|y| (0..12).all(|x| a.color_at(x, y) == b.color_at(x, y)))
+	}
+
+	#[test]
+	fn offset() {
+		// Starting on an odd column of RGGB makes it GRBG, odd row GBRG, and both BGGR
+		assert!(same(&CFA::RGGB.offset(1, 0), &CFA::GRBG));
+		assert!(same(&CFA::RGGB.offset(0, 1), &CFA::GBRG));
+		assert!(same(&CFA::RGGB.offset(1, 1), &CFA::BGGR));
+		assert!(same(&CFA::RGGB.offset(2, 4), &CFA::RGGB));
+
+		// Offsets add up
+		assert_eq!(CFA::RGGB.offset(1, 0).offset(0, 1), CFA::RGGB.offset(1, 1));
+	}
+
+	#[test]
+	fn rotate() {
+		// An even sized image keeps the phase of its corners
+		assert!(same(&CFA::RGGB.rotate_180(4, 4), &CFA::BGGR));
+		assert!(same(&CFA::RGGB.rotate_cw(4), &CFA::GRBG));
+		assert!(same(&CFA::RGGB.rotate_ccw(4), &CFA::GBRG));
+
+		// But an odd one doesn't
+		assert!(same(&CFA::RGGB.rotate_180(5, 3), &CFA::RGGB));
+		assert!(same(&CFA::RGGB.rotate_cw(3), &CFA::RGGB));
+
+		// Four turns is where we started, and a turn each way cancels out
+		let xtrans = CFA::xtrans([[Color::Green, Color::Red, Color::Blue, Color::Green, Color::Blue, Color::Red]; 6]);
+		assert!(same(&xtrans.rotate_cw(7).rotate_cw(9).rotate_cw(7).rotate_cw(9), &xtrans));
+		assert!(same(&xtrans.rotate_cw(7).rotate_ccw(7), &xtrans));
+	}
+}
@@ -60,4 +60,63 @@ impl From<Color> for usize {
 			}
 		}
 	}
-}
\ No newline at end of file
+}
+
+/// Components of the YCbCr kind of image
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum YCbCrComponent {
+	/// Luma
+	Y,
+	/// Blue difference
+	Cb,
+	/// Red difference
+	Cr
+}
+
+impl fmt::Display for YCbCrComponent {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			YCbCrComponent::Y => write!(f, "Y"),
+			YCbCrComponent::Cb => write!(f, "Cb"),
+			YCbCrComponent::Cr => write!(f, "Cr")
+		}
+	}
+}
+
+impl From<YCbCrComponent> for usize {
+	fn from(c: YCbCrComponent) -> usize {
+		match c {
+			YCbCrComponent::Y => 0,
+			YCbCrComponent::Cb => 1,
+			YCbCrComponent::Cr => 2
+		}
+	}
+}
+
+/// Components of the Hsl kind of image
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum HslComponent {
+	Hue,
+	Saturation,
+	Lightness
+}
+
+impl fmt::Display for HslComponent {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			HslComponent::Hue => write!(f, "hue"),
+			HslComponent::Saturation => write!(f, "saturation"),
+			HslComponent::Lightness => write!(f, "lightness")
+		}
+	}
+}
+
+impl From<HslComponent> for usize {
+	fn from(c: HslComponent) -> usize {
+		match c {
+			HslComponent::Hue => 0,
+			HslComponent::Saturation => 1,
+			HslComponent::Lightness => 2
+		}
+	}
+}
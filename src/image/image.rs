@@ -1,10 +1,11 @@
-use crate::image::Color;
+use crate::image::{Attribute, Color, HslComponent, YCbCrComponent};
 use std::ops::Range;
 use std::iter::StepBy;
 use crate::CFA;
 use libraw::Colordata;
 use num_traits::{Num, PrimInt, AsPrimitive};
 use crate::Processor;
+use crate::color_management::{Chromaticity, Primaries};
 use std::iter::Skip;
 use std::slice::IterMut;
 
@@ -12,21 +13,116 @@ pub struct Metadata {
 	pub width: u32,
 	pub height: u32,
 	pub cfa: CFA,
+	/// How many bits the decoder read for each photosite, from libraw's
+	/// `raw_bps`. Not every format sets that, and then it's the fewest bits
+	/// that hold the white level, or 16 if there isn't one. It's what
+	/// [Image::to_floats] scales by, and
+	/// [Image::to_floats_white](crate::image::Image::to_floats_white) or
+	/// [Image::to_floats_levels](crate::image::Image::to_floats_levels) are
+	/// better when the white level is what matters.
 	pub bit_depth: u8,
+	/// Black level of each color, indexed by [Color]
+	pub black_levels: [u16; 3],
+	/// The value a photosite saturates at
+	pub white_level: u16,
+	/// Where each color, indexed by [Color], saturated in the units of the
+	/// data. It starts at the white level and follows the data through
+	/// scaling, black levels, and white balance.
+	pub clip_levels: [f32; 3],
+	/// Keep float data linear and unclamped through processing, so values can
+	/// go above 1.0 and below 0.0. It's only clamped when it's output, by
+	/// [Processor::clamp](crate::Processor::clamp) or to_bytes. When this is
+	/// false, which is the default, negative values are dropped as they come
+	/// up and the color and tone steps clamp to 0.0 through 1.0.
+	pub scene_referred: bool,
+	/// The primaries and white point of linear Rgb data, which conversions
+	/// to Xyz and the kinds from it go through. It's sRGB until
+	/// [Processor::to_color_space](crate::Processor::to_color_space) sets it.
+	pub primaries: Primaries,
 	pub colordata: Colordata
 }
 
 impl Metadata {
+	/// The bit depth, black levels and white level are all taken from the
+	/// colordata
 	pub fn new(width: u32, height: u32, cfa: CFA, colordata: Colordata) -> Self {
+		let white_level = colordata.maximum.min(u16::MAX as u32) as u16;
+
 		Self {
 			width,
 			height,
+			black_levels: Self::colordata_black_levels(&cfa, &colordata),
+			white_level,
+			clip_levels: [white_level as f32; 3],
+			scene_referred: false,
+			primaries: Primaries::SRGB,
+			bit_depth: Self::colordata_bit_depth(&colordata),
 			cfa,
-			bit_depth: 12, //TODO: Allow changing bit depth
 			colordata
 		}
 	}
 
+	// See the field
+	fn colordata_bit_depth(colordata: &Colordata) -> u8 {
+		let white_level = colordata.maximum.min(u16::MAX as u32) as u16;
+
+		match colordata.raw_bps {
+			1..=16 => colordata.raw_bps as u8,
+			_ if white_level > 0 => (16 - white_level.leading_zeros()) as u8,
+			_ => 16
+		}
+	}
+
+	// libraw keeps an overall black level in `black` and one per color in the
+	// first four of `cblack`, where 0 is red, 2 blue, and 1 and 3 are green. It
+	// can also have a pattern of black levels, `cblack[4]` rows by `cblack[5]`
+	// columns, starting at `cblack[6]` which we average per color of the CFA.
+	fn colordata_black_levels(cfa: &CFA, colordata: &Colordata) -> [u16; 3] {
+		let cblack = &colordata.cblack;
+		let mut levels = [
+			cblack[0] as f32,
+			(cblack[1] + cblack[3]) as f32 / 2.0,
+			cblack[2] as f32
+		];
+
+		let (rows, cols) = (cblack[4], cblack[5]);
+		if rows > 0 && cols > 0 && 6 + (rows * cols) as usize <= cblack.len() {
+			let mut sums = [0.0; 3];
+			let mut counts = [0.0; 3];
+
+			// Go over enough of the pattern that it lines up with the CFA again
+			for row in 0..rows * cfa.height() {
+				for col in 0..cols * cfa.width() {
+					let color = cfa.color_at(col, row) as usize;
+					sums[color] += cblack[(6 + (row % rows) * cols + col % cols) as usize] as f32;
+					counts[color] += 1.0;
+				}
+			}
+
+			for color in 0..3 {
+				if counts[color] > 0.0 {
+					levels[color] += sums[color] / counts[color];
+				}
+			}
+		}
+
+		let black = colordata.black as f32;
+		[
+			(black + levels[0]).round() as u16,
+			(black + levels[1]).round() as u16,
+			(black + levels[2]).round() as u16
+		]
+	}
+
+	/// The white of the data, from its primaries
+	pub fn white_point(&self) -> Chromaticity {
+		self.primaries.white
+	}
+
+	pub fn black_level(&self, color: Color) -> u16 {
+		self.black_levels[color as usize]
+	}
+
 	pub fn xytoi(&self, x: u32, y: u32) -> usize {
 		(y * self.width + x) as usize
 	}
@@ -51,11 +147,71 @@ impl Metadata {
 		self.cfa.color_at(x, y)
 	}
 
+	/// The largest value of the bit depth
 	pub fn depth_max(&self) -> usize {
 		2.pow(self.bit_depth as u32) - 1
 	}
 }
 
+/// A rectangular area of an image
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Rect {
+	pub x: u32,
+	pub y: u32,
+	pub width: u32,
+	pub height: u32
+}
+
+impl Rect {
+	pub fn contains(&self, x: u32, y: u32) -> bool {
+		x >= self.x && x < self.x + self.width && y >= self.y && y < self.y + self.height
+	}
+}
+
+/// The optically masked photosites around the active area of a sensor. They
+/// only ever see black, which makes them good for estimating black levels.
+pub struct MaskedBorder {
+	/// The whole sensor, border and active area both
+	pub raw: Image<Sensor, u16>,
+	/// Where the active area is in `raw`
+	pub active: Rect
+}
+
+impl MaskedBorder {
+	/// Every photosite outside of the active area along with its color
+	pub fn photosites(&self) -> impl Iterator<Item = (Color, u16)> + '_ {
+		self.raw.pixel_range().filter_map(move |i| {
+			let (x, y) = self.raw.meta.itoxy(i);
+
+			if self.active.contains(x, y) {
+				None
+			} else {
+				Some((self.raw.meta.color_at_xy(x, y), self.raw.data[i]))
+			}
+		})
+	}
+}
+
+#[cfg(test)]
+impl Metadata {
+	/// Metadata for a camera libraw knows nothing about, for tests
+	pub(crate) fn test(width: u32, height: u32, cfa: CFA) -> Self {
+		// Colordata is libraw's C struct of plain numbers, and all zeroes is
+		// what it has for a camera it doesn't know
+		Self::new(width, height, cfa, unsafe { std::mem::zeroed() })
+	}
+
+	/// Metadata with the Canon EOS 5D Mark II's camera matrix, as libraw has
+	/// it, for tests
+	pub(crate) fn test_camera(width: u32, height: u32, cfa: CFA) -> Self {
+		let mut meta = Self::test(width, height, cfa);
+		meta.colordata.cam_xyz[0] = [0.4716, 0.0603, -0.0830];
+		meta.colordata.cam_xyz[1] = [-0.7798, 1.5474, 0.2480];
+		meta.colordata.cam_xyz[2] = [-0.1496, 0.1937, 0.6651];
+		meta
+	}
+}
+
 pub trait Kind {
 	fn per_pixel() -> usize;
 }
@@ -81,6 +237,49 @@ impl Kind for Hsv {
 	}
 }
 
+/// Hue, saturation, and lightness. Hue is in degrees.
+pub struct Hsl;
+impl Kind for Hsl {
+	fn per_pixel() -> usize {
+		3
+	}
+}
+
+/// Luma and the blue and red differences, with the luma coefficients of
+/// `matrix`. Y is 0 to 1 and Cb and Cr are -0.5 to 0.5. It's made from RGB
+/// as it is, which for video is after the transfer function.
+pub struct YCbCr {
+	pub matrix: YCbCrMatrix
+}
+impl Kind for YCbCr {
+	fn per_pixel() -> usize {
+		3
+	}
+}
+
+/// The standards YCbCr comes from, which each weigh the colors differently
+/// in luma
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum YCbCrMatrix {
+	/// ITU-R BT.601, standard definition video and JPEG
+	Bt601,
+	/// ITU-R BT.709, high definition video
+	Bt709,
+	/// ITU-R BT.2020, UHD and HDR video
+	Bt2020
+}
+
+impl YCbCrMatrix {
+	/// How much red and blue are of luma. Green is the rest.
+	pub fn coefficients(&self) -> (f32, f32) {
+		match self {
+			YCbCrMatrix::Bt601 => (0.299, 0.114),
+			YCbCrMatrix::Bt709 => (0.2126, 0.0722),
+			YCbCrMatrix::Bt2020 => (0.2627, 0.0593)
+		}
+	}
+}
+
 pub struct Gray;
 impl Kind for Gray {
 	fn per_pixel() -> usize {
@@ -88,6 +287,42 @@ impl Kind for Gray {
 	}
 }
 
+/// CIE 1931 XYZ, relative to the white point in the metadata, where white
+/// has a Y of 1.0
+pub struct Xyz;
+impl Kind for Xyz {
+	fn per_pixel() -> usize {
+		3
+	}
+}
+
+/// CIE 1976 L*a*b*, relative to the white point in the metadata. L is 0 to
+/// 100.
+pub struct Lab;
+impl Kind for Lab {
+	fn per_pixel() -> usize {
+		3
+	}
+}
+
+/// Lightness, chroma, and hue of [Lab]. Hue is in degrees.
+pub struct Lch;
+impl Kind for Lch {
+	fn per_pixel() -> usize {
+		3
+	}
+}
+
+/// Björn Ottosson's Oklab, which is more perceptually even than Lab,
+/// particularly in hue. L is 0 to 1. It's defined for a D65 white, so other
+/// white points are adapted to it.
+pub struct Oklab;
+impl Kind for Oklab {
+	fn per_pixel() -> usize {
+		3
+	}
+}
+
 pub trait Component: Num + Copy {}
 impl<T: Num + Copy> Component for T {}
 
@@ -117,6 +352,87 @@ impl<K: Kind, T: Component> Image<K, T> {
 	pub fn data_range(&self) -> Range<usize> {
 		0..self.data.len()
 	}
+
+	/// Cut out the `width` by `height` area starting at x,y. The CFA is
+	/// offset so it still matches the data.
+	///
+	/// Panics if the area isn't inside the image.
+	pub fn crop(self, x: u32, y: u32, width: u32, height: u32) -> Self {
+		assert!(
+			x + width <= self.meta.width && y + height <= self.meta.height,
+			"crop of {}x{} at {},{} is outside the {}x{} image",
+			width, height, x, y, self.meta.width, self.meta.height
+		);
+
+		let per_pixel = K::per_pixel();
+		let mut data = Vec::with_capacity(width as usize * height as usize * per_pixel);
+		for row in y..y+height {
+			let start = self.meta.xytoi(x, row) * per_pixel;
+			data.extend_from_slice(&self.data[start..start + width as usize * per_pixel]);
+		}
+
+		Image {
+			kind: self.kind,
+			data,
+			meta: Metadata {
+				width,
+				height,
+				cfa: self.meta.cfa.offset(x, y),
+				..self.meta
+			}
+		}
+	}
+
+	/// Turn the image upside down
+	pub fn rotate_180(self) -> Self {
+		let (width, height) = (self.meta.width, self.meta.height);
+		let cfa = self.meta.cfa.rotate_180(width, height);
+
+		self.remap(width, height, cfa, |x, y| (width - 1 - x, height - 1 - y))
+	}
+
+	/// Rotate the image 90 degrees clockwise
+	pub fn rotate_cw(self) -> Self {
+		let (width, height) = (self.meta.width, self.meta.height);
+		let cfa = self.meta.cfa.rotate_cw(height);
+
+		self.remap(height, width, cfa, |x, y| (y, height - 1 - x))
+	}
+
+	/// Rotate the image 90 degrees counter-clockwise
+	pub fn rotate_ccw(self) -> Self {
+		let (width, height) = (self.meta.width, self.meta.height);
+		let cfa = self.meta.cfa.rotate_ccw(width);
+
+		self.remap(height, width, cfa, |x, y| (width - 1 - y, x))
+	}
+
+	// Build a `width` by `height` image where every pixel is copied from the
+	// one `source` gives for it
+	fn remap<F>(self, width: u32, height: u32, cfa: CFA, source: F) -> Self
+	where F: Fn(u32, u32) -> (u32, u32)
+	{
+		let per_pixel = K::per_pixel();
+		let mut data = Vec::with_capacity(self.data.len());
+		for y in 0..height {
+			for x in 0..width {
+				let (sx, sy) = source(x, y);
+				let start = self.meta.xytoi(sx, sy) * per_pixel;
+				data.extend_from_slice(&self.data[start..start + per_pixel]);
+			}
+		}
+
+		Image {
+			kind: self.kind,
+			data,
+			meta: Metadata {
+				width,
+				height,
+				cfa,
+				..self.meta
+			}
+		}
+	}
 }
 
 impl<T: Component> Image<Rgb, T> {
@@ -133,9 +449,54 @@ impl<T: Component> Image<Rgb, T> {
 	}
 }
 
+impl<T: Component> Image<Hsv, T> {
+	pub fn attribute(&self, x: u32, y: u32, attribute: Attribute) -> T {
+		self.data[self.meta.xytoi(x, y) * Hsv::per_pixel() + usize::from(attribute)]
+	}
+
+	pub fn set_attribute(&mut self, i: usize, attribute: Attribute, value: T) {
+		self.data[i * Hsv::per_pixel() + usize::from(attribute)] = value;
+	}
+}
+
+impl<T: Component> Image<Hsl, T> {
+	pub fn component(&self, x: u32, y: u32, component: HslComponent) -> T {
+		self.data[self.meta.xytoi(x, y) * Hsl::per_pixel() + usize::from(component)]
+	}
+
+	pub fn set_component(&mut self, i: usize, component: HslComponent, value: T) {
+		self.data[i * Hsl::per_pixel() + usize::from(component)] = value;
+	}
+}
+
+impl<T: Component> Image<YCbCr, T> {
+	pub fn component(&self, x: u32, y: u32, component: YCbCrComponent) -> T {
+		self.data[self.meta.xytoi(x, y) * YCbCr::per_pixel() + usize::from(component)]
+	}
+
+	pub fn set_component(&mut self, i: usize, component: YCbCrComponent, value: T) {
+		self.data[i * YCbCr::per_pixel() + usize::from(component)] = value;
+	}
+}
+
 impl<K: Kind, I: Component + PrimInt + AsPrimitive<f32>> Image<K, I> {
 	pub fn to_floats(self) -> Image<K, f32> {
 		let max = self.meta.depth_max() as f32;
+		self.scale_floats(max)
+	}
+
+	/// Like [Image::to_floats], but the white level becomes 1.0 instead of the
+	/// bit depth's maximum. Meant for data that's already had its black levels
+	/// taken away with [Processor::black_levels_from_meta].
+	pub fn to_floats_white(self) -> Image<K, f32> {
+		let max = self.meta.white_level.max(1) as f32;
+		self.scale_floats(max)
+	}
+
+	fn scale_floats(mut self, max: f32) -> Image<K, f32> {
+		for level in self.meta.clip_levels.iter_mut() {
+			*level /= max;
+		}
 
 		Image {
 			kind: self.kind,
@@ -147,12 +508,48 @@ impl<K: Kind, I: Component + PrimInt + AsPrimitive<f32>> Image<K, I> {
 	}
 }
 
+impl<I: Component + PrimInt + AsPrimitive<f32>> Image<Sensor, I> {
+	/// Convert to floats where each color's black level is 0.0 and the white
+	/// level is 1.0. Anything below the black level becomes 0.0, unless the
+	/// metadata is scene referred.
+	pub fn to_floats_levels(mut self) -> Image<Sensor, f32> {
+		let white = self.meta.white_level as f32;
+		let black = [
+			self.meta.black_levels[0] as f32,
+			self.meta.black_levels[1] as f32,
+			self.meta.black_levels[2] as f32
+		];
+
+		for (level, black) in self.meta.clip_levels.iter_mut().zip(black.iter()) {
+			*level = (*level - black) / (white - black).max(1.0);
+		}
+
+		let meta = &self.meta;
+		let data = self.data.into_iter().enumerate().map(|(i, x)| -> f32 {
+			let black = black[meta.color_at_index(i) as usize];
+			let light = (x.as_() - black) / (white - black).max(1.0);
+
+			if meta.scene_referred {
+				light
+			} else {
+				light.max(0.0)
+			}
+		}).collect();
+
+		Image {
+			kind: self.kind,
+			data,
+			meta: self.meta
+		}
+	}
+}
+
 impl<K: Kind> Image<K, f32> {
 	pub fn to_bytes(self) -> Image<K, u8> {
 		Image {
 			kind: self.kind,
 			data: self.data.into_iter().map(|x| -> u8 {
-					(x * 255.0) as u8
+					(x.clamp(0.0, 1.0) * 255.0) as u8
 				}).collect(),
 			meta: self.meta
 		}
@@ -171,6 +568,31 @@ impl From<Image<Hsv, f32>> for Image<Rgb, f32> {
 	}
 }
 
+impl From<Image<Rgb, f32>> for Image<Hsl, f32> {
+	fn from(rgb: Image<Rgb, f32>) -> Image<Hsl, f32> {
+		Processor::rgb_to_hsl(rgb)
+	}
+}
+
+impl From<Image<Hsl, f32>> for Image<Rgb, f32> {
+	fn from(hsl: Image<Hsl, f32>) -> Image<Rgb, f32> {
+		Processor::hsl_to_rgb(hsl)
+	}
+}
+
+/// With BT.709. Use [Processor::rgb_to_ycbcr] for the others.
+impl From<Image<Rgb, f32>> for Image<YCbCr, f32> {
+	fn from(rgb: Image<Rgb, f32>) -> Image<YCbCr, f32> {
+		Processor::rgb_to_ycbcr(rgb, YCbCrMatrix::Bt709)
+	}
+}
+
+impl From<Image<YCbCr, f32>> for Image<Rgb, f32> {
+	fn from(ycbcr: Image<YCbCr, f32>) -> Image<Rgb, f32> {
+		Processor::ycbcr_to_rgb(ycbcr)
+	}
+}
+
 impl<T: Component> From<Image<Hsv, T>> for Image<Gray, T> {
 	fn from(hsv: Image<Hsv, T>) -> Image<Gray, T> {
 		Image {
@@ -179,4 +601,176 @@ impl<T: Component> From<Image<Hsv, T>> for Image<Gray, T> {
 			meta: hsv.meta
 		}
 	}
-}
\ No newline at end of file
+}
+
+// Rgb is taken to be linear, in the primaries in the metadata
+
+impl From<Image<Rgb, f32>> for Image<Xyz, f32> {
+	fn from(rgb: Image<Rgb, f32>) -> Image<Xyz, f32> {
+		Processor::rgb_to_xyz(rgb)
+	}
+}
+
+impl From<Image<Xyz, f32>> for Image<Rgb, f32> {
+	fn from(xyz: Image<Xyz, f32>) -> Image<Rgb, f32> {
+		Processor::xyz_to_rgb(xyz)
+	}
+}
+
+impl From<Image<Xyz, f32>> for Image<Lab, f32> {
+	fn from(xyz: Image<Xyz, f32>) -> Image<Lab, f32> {
+		Processor::xyz_to_lab(xyz)
+	}
+}
+
+impl From<Image<Lab, f32>> for Image<Xyz, f32> {
+	fn from(lab: Image<Lab, f32>) -> Image<Xyz, f32> {
+		Processor::lab_to_xyz(lab)
+	}
+}
+
+impl From<Image<Lab, f32>> for Image<Lch, f32> {
+	fn from(lab: Image<Lab, f32>) -> Image<Lch, f32> {
+		Processor::lab_to_lch(lab)
+	}
+}
+
+impl From<Image<Lch, f32>> for Image<Lab, f32> {
+	fn from(lch: Image<Lch, f32>) -> Image<Lab, f32> {
+		Processor::lch_to_lab(lch)
+	}
+}
+
+impl From<Image<Xyz, f32>> for Image<Oklab, f32> {
+	fn from(xyz: Image<Xyz, f32>) -> Image<Oklab, f32> {
+		Processor::xyz_to_oklab(xyz)
+	}
+}
+
+impl From<Image<Oklab, f32>> for Image<Xyz, f32> {
+	fn from(oklab: Image<Oklab, f32>) -> Image<Xyz, f32> {
+		Processor::oklab_to_xyz(oklab)
+	}
+}
+
+impl From<Image<Rgb, f32>> for Image<Lab, f32> {
+	fn from(rgb: Image<Rgb, f32>) -> Image<Lab, f32> {
+		Image::<Xyz, f32>::from(rgb).into()
+	}
+}
+
+impl From<Image<Lab, f32>> for Image<Rgb, f32> {
+	fn from(lab: Image<Lab, f32>) -> Image<Rgb, f32> {
+		Image::<Xyz, f32>::from(lab).into()
+	}
+}
+
+impl From<Image<Rgb, f32>> for Image<Lch, f32> {
+	fn from(rgb: Image<Rgb, f32>) -> Image<Lch, f32> {
+		Image::<Lab, f32>::from(rgb).into()
+	}
+}
+
+impl From<Image<Lch, f32>> for Image<Rgb, f32> {
+	fn from(lch: Image<Lch, f32>) -> Image<Rgb, f32> {
+		Image::<Lab, f32>::from(lch).into()
+	}
+}
+
+impl From<Image<Rgb, f32>> for Image<Oklab, f32> {
+	fn from(rgb: Image<Rgb, f32>) -> Image<Oklab, f32> {
+		Image::<Xyz, f32>::from(rgb).into()
+	}
+}
+
+impl From<Image<Oklab, f32>> for Image<Rgb, f32> {
+	fn from(oklab: Image<Oklab, f32>) -> Image<Rgb, f32> {
+		Image::<Xyz, f32>::from(oklab).into()
+	}
+}
+
+#[cfg(test)]
+mod image_tests {
+	use super::*;
+
+	// RGGB photosites of a sensor with a white level of 1000 and black levels
+	// of 100, 110 and 120
+	fn sensor() -> Image<Sensor, u16> {
+		let mut meta = Metadata::test(2, 2, CFA::RGGB);
+		meta.white_level = 1000;
+		meta.clip_levels = [1000.0; 3];
+		meta.black_levels = [100, 110, 120];
+
+		Image { kind: Sensor, data: vec![550, 110, 50, 1000], meta }
+	}
+
+	#[test]
+	fn colordata_levels() {
+		// A camera libraw knows nothing about
+		let mut meta = Metadata::test(4, 4, CFA::RGGB);
+		assert_eq!((meta.white_level, meta.black_levels, meta.bit_depth), (0, [0; 3], 16));
+		assert_eq!(meta.depth_max(), 65535);
+
+		// Green is the mean of its two
+		meta.colordata.maximum = 15000;
+		meta.colordata.black = 500;
+		meta.colordata.cblack[..4].copy_from_slice(&[10, 20, 60, 40]);
+		let mut meta = Metadata::new(4, 4, CFA::RGGB, meta.colordata);
+
+		assert_eq!(meta.white_level, 15000);
+		assert_eq!(meta.clip_levels, [15000.0; 3]);
+		assert_eq!(meta.black_levels, [510, 530, 560]);
+
+		// The decoder's bit depth, and the white level's if it doesn't say
+		assert_eq!(meta.bit_depth, 14);
+		meta.colordata.raw_bps = 12;
+		assert_eq!(Metadata::colordata_bit_depth(&meta.colordata), 12);
+		meta.colordata.raw_bps = 0;
+		meta.colordata.maximum = 4095;
+		assert_eq!(Metadata::colordata_bit_depth(&meta.colordata), 12);
+	}
+
+	#[test]
+	fn colordata_black_pattern() {
+		// A 2x2 pattern that lines up with RGGB, so each photosite has its own
+		let mut colordata = Metadata::test(4, 4, CFA::RGGB).colordata;
+		colordata.black = 100;
+		colordata.cblack[4..8].copy_from_slice(&[2, 2, 4, 6]);
+		colordata.cblack[8..10].copy_from_slice(&[8, 12]);
+		assert_eq!(Metadata::colordata_black_levels(&CFA::RGGB, &colordata), [104, 107, 112]);
+
+		// Two rows of one, so the greens are averaged over both and the pattern
+		// is folded into the per color levels
+		colordata.cblack[..6].copy_from_slice(&[1, 1, 1, 1, 2, 1]);
+		colordata.cblack[6..8].copy_from_slice(&[2, 10]);
+		assert_eq!(Metadata::colordata_black_levels(&CFA::RGGB, &colordata), [103, 107, 111]);
+
+		// Too big to fit in cblack is ignored
+		colordata.cblack[4..6].copy_from_slice(&[100, 100]);
+		assert_eq!(Metadata::colordata_black_levels(&CFA::RGGB, &colordata), [101, 101, 101]);
+	}
+
+	#[test]
+	fn to_floats_white() {
+		let mut img = sensor();
+		img.meta.white_level = 800;
+		img.meta.clip_levels = [800.0, 400.0, 200.0];
+
+		let img = img.to_floats_white();
+		assert_eq!(img.data, vec![550.0 / 800.0, 110.0 / 800.0, 50.0 / 800.0, 1.25]);
+		assert_eq!(img.meta.clip_levels, [1.0, 0.5, 0.25]);
+	}
+
+	#[test]
+	fn to_floats_levels() {
+		let img = sensor().to_floats_levels();
+		assert_eq!(img.data, vec![0.5, 0.0, 0.0, 1.0]);
+		assert_eq!(img.meta.clip_levels, [1.0; 3]);
+
+		// Below black stays negative when scene referred
+		let mut img = sensor();
+		img.meta.scene_referred = true;
+		let img = img.to_floats_levels();
+		assert_eq!(img.data[2], -60.0 / 890.0);
+	}
+}
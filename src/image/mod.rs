@@ -2,6 +2,6 @@ mod component;
 mod cfa;
 mod image;
 
-pub use component::{Attribute, Color};
+pub use component::{Attribute, Color, HslComponent, YCbCrComponent};
 pub use cfa::CFA;
-pub use self::image::{Metadata, Image, Component, Sensor, Rgb, Hsv, Gray};
\ No newline at end of file
+pub use self::image::{Metadata, Image, Component, Kind, Sensor, Rgb, Hsv, Hsl, YCbCr, YCbCrMatrix, Gray, Xyz, Lab, Lch, Oklab, Rect, MaskedBorder};
\ No newline at end of file
@@ -1,58 +1,246 @@
+mod color;
+mod error;
+mod green;
+mod highlights;
 mod processor;
+pub mod color_management;
 pub mod debayer;
 pub mod image;
+#[cfg(test)]
+mod test_util;
 
-pub use processor::Processor;
+pub use error::Error;
+pub use highlights::Highlights;
+pub use processor::{AutoWhiteBalance, ColorSpace, Processor, Transfer, WhiteBalance, WhiteBalanceCells};
 
-use crate::image::{CFA, Metadata, Image, Sensor};
+use crate::image::{CFA, MaskedBorder, Metadata, Image, Rect, Sensor};
+use std::io::Read;
+use std::path::Path;
+
+/// Read and decode the raw file at `path`
+pub fn read_file<P: AsRef<Path>>(path: P) -> Result<Image<Sensor, u16>, Error> {
+	read_bytes(&std::fs::read(path)?)
+}
+
+/// Read a raw file to the end of `reader` and decode it
+pub fn read_from<R: Read>(mut reader: R) -> Result<Image<Sensor, u16>, Error> {
+	let mut data = vec![];
+	reader.read_to_end(&mut data)?;
+
+	read_bytes(&data)
+}
+
+/// Decode a raw file that's already in memory
+pub fn read_bytes(data: &[u8]) -> Result<Image<Sensor, u16>, Error> {
+	decode(data, false).map(|(image, _)| image)
+}
+
+/// Like [read_file], but also keeps the masked border of the sensor. It's None
+/// if the sensor doesn't have one.
+pub fn read_file_with_border<P: AsRef<Path>>(path: P) -> Result<(Image<Sensor, u16>, Option<MaskedBorder>), Error> {
+	read_bytes_with_border(&std::fs::read(path)?)
+}
+
+/// Like [read_bytes], but also keeps the masked border of the sensor. It's None
+/// if the sensor doesn't have one.
+pub fn read_bytes_with_border(data: &[u8]) -> Result<(Image<Sensor, u16>, Option<MaskedBorder>), Error> {
+	decode(data, true)
+}
+
+fn decode(data: &[u8], keep_border: bool) -> Result<(Image<Sensor, u16>, Option<MaskedBorder>), Error> {
+	if data.is_empty() {
+		return Err(Error::UnsupportedFormat);
+	}
 
-pub fn read_file(filename: &str) -> Image<Sensor, u16> {
-	// Raw NEF data
-	let nef_data = std::fs::read(filename).expect("Failed to read in raw file");
 	let decoder = libraw::Processor::new();
 
 	// Decode into raw sensor data
-	let decoded = decoder.decode(&nef_data).expect("Failed to decode raw file");
+	let decoded = decoder.decode(data)?;
 	let sensor_data = (*decoded).to_vec();
 	let sizes = decoded.sizes();
 
-	let raw_size = sizes.raw_width as usize * sizes.raw_height as usize;
-	let image_size = sizes.width as usize * sizes.height as usize;
+	// libraw describes the CFA starting from the top left of the active area
+	let cfa = read_cfa(&decoded.idata())?;
 
-	// TODO: Move to own function, call `extract_meaningful_image` maybe?
-	if raw_size != image_size {
-		let mut image = Vec::with_capacity(image_size);
+	let active = Rect {
+		x: sizes.left_margin as u32,
+		y: sizes.top_margin as u32,
+		width: sizes.width as u32,
+		height: sizes.height as u32
+	};
 
-		// FIXME: Assumes the extra data is to the right and/or bottom
-		for row in 0..sizes.height as usize {
-			let lower = row * sizes.raw_width as usize;
-			let upper = lower + sizes.width as usize;
+	crop(
+		sensor_data,
+		sizes.raw_width as u32,
+		sizes.raw_height as u32,
+		active,
+		cfa,
+		keep_border,
+		|width, height, cfa| Metadata::new(width, height, cfa, decoded.color())
+	)
+}
 
-			image.extend_from_slice(&sensor_data[lower..upper]);
-		}
+// Cut the active area out of the full sensor's data, and keep all of it as
+// the masked border if asked to. `cfa` starts from the top left of the active
+// area, like libraw describes it. `meta` makes the metadata for a size and CFA.
+fn crop<M>(sensor_data: Vec<u16>, raw_width: u32, raw_height: u32, active: Rect, cfa: CFA, keep_border: bool, meta: M) -> Result<(Image<Sensor, u16>, Option<MaskedBorder>), Error>
+where M: Fn(u32, u32, CFA) -> Metadata
+{
+	if sensor_data.is_empty() {
+		return Err(Error::UnsupportedFormat);
+	} else if sensor_data.len() != raw_width as usize * raw_height as usize
+		|| active.x + active.width > raw_width
+		|| active.y + active.height > raw_height
+		|| active.width == 0
+		|| active.height == 0
+	{
+		return Err(Error::BadDimensions {
+			width: raw_width,
+			height: raw_height,
+			len: sensor_data.len()
+		});
+	}
 
-		//FIXME: Assumes CFA:RGGB
-		Image {
-			kind: Sensor {},
-			data: image,
-			meta: Metadata::new(
-				sizes.width as u32, 
-				sizes.height as u32,
-				CFA::RGGB,
-				decoded.color()
-			)
-		}
-	} else {
-		//FIXME: Assumes CFA:RGGB
-		Image {
+	if active.width == raw_width && active.height == raw_height {
+		let image = Image {
 			kind: Sensor {},
 			data: sensor_data,
-			meta: Metadata::new(
-				sizes.raw_width as u32, 
-				sizes.raw_height as u32,
-				CFA::RGGB,
-				decoded.color()
-			)
+			meta: meta(raw_width, raw_height, cfa)
+		};
+
+		return Ok((image, None));
+	}
+
+	// Copy the active area out of the full sensor
+	let mut image = Vec::with_capacity(active.width as usize * active.height as usize);
+	for row in active.y..active.y + active.height {
+		let lower = (row * raw_width + active.x) as usize;
+		let upper = lower + active.width as usize;
+
+		image.extend_from_slice(&sensor_data[lower..upper]);
+	}
+
+	let border = if keep_border {
+		// The full sensor starts before the active area, so the CFA has to be
+		// moved back by the margins
+		let raw_cfa = cfa.offset(
+			cfa.width() - active.x % cfa.width(),
+			cfa.height() - active.y % cfa.height()
+		);
+
+		Some(MaskedBorder {
+			raw: Image {
+				kind: Sensor {},
+				data: sensor_data,
+				meta: meta(raw_width, raw_height, raw_cfa)
+			},
+			active
+		})
+	} else {
+		None
+	};
+
+	let image = Image {
+		kind: Sensor {},
+		data: image,
+		meta: meta(active.width, active.height, cfa)
+	};
+
+	Ok((image, border))
+}
+
+// Figure out the color filter array from libraw's description of the sensor
+fn read_cfa(idata: &libraw::Idata) -> Result<CFA, Error> {
+	if idata.filters == 0 {
+		// Not mosaiced at all, like linear DNGs
+		return Err(Error::UnsupportedFormat);
+	} else if idata.colors != 3 {
+		return Err(Error::UnsupportedCfa(format!("sensor has {} colors", idata.colors)));
+	}
+
+	// libraw uses the magic value 9 to mean there's a 6x6 X-Trans pattern, and
+	// anything else below 1000 is a pattern it can't describe in `filters`
+	if idata.filters == 9 {
+		CFA::from_xtrans(&idata.xtrans).ok_or_else(|| {
+			Error::UnsupportedCfa(format!("X-Trans pattern {:?}", idata.xtrans))
+		})
+	} else if idata.filters < 1000 {
+		Err(Error::UnsupportedCfa(format!("filter pattern {}", idata.filters)))
+	} else {
+		Ok(CFA::from_filters(idata.filters))
+	}
+}
+
+#[cfg(test)]
+mod crop_tests {
+	use super::*;
+	use crate::image::Color;
+
+	// Every photosite is its own index, so where it came from can be seen
+	fn sensor(width: u32, height: u32) -> Vec<u16> {
+		(0..(width * height) as u16).collect()
+	}
+
+	fn active(x: u32, y: u32, width: u32, height: u32) -> Rect {
+		Rect { x, y, width, height }
+	}
+
+	#[test]
+	fn crops_margins() {
+		let (image, border) = crop(sensor(8, 6), 8, 6, active(2, 1, 4, 3), CFA::RGGB, false, Metadata::test).unwrap();
+
+		assert_eq!((image.meta.width, image.meta.height), (4, 3));
+		assert_eq!(image.data, vec![10, 11, 12, 13, 18, 19, 20, 21, 26, 27, 28, 29]);
+		assert!(border.is_none());
+
+		// The CFA is libraw's from the top left of the active area, so it's kept
+		assert_eq!(image.meta.color_at_xy(0, 0), Color::Red);
+
+		// No margins and nothing to crop
+		let (image, border) = crop(sensor(4, 2), 4, 2, active(0, 0, 4, 2), CFA::RGGB, true, Metadata::test).unwrap();
+		assert_eq!(image.data, sensor(4, 2));
+		assert!(border.is_none());
+	}
+
+	#[test]
+	fn border_phase() {
+		// Even and odd margins, which flip a Bayer CFA's phase, and X-Trans
+		// margins that aren't a multiple of its 6x6
+		let cases = [
+			(CFA::RGGB, active(2, 2, 4, 2), Color::Red),
+			(CFA::RGGB, active(1, 0, 4, 2), Color::Green),
+			(CFA::RGGB, active(1, 1, 4, 2), Color::Blue),
+			(CFA::GBRG, active(0, 3, 4, 2), Color::Red),
+			(CFA::test_xtrans(), active(4, 5, 6, 6), CFA::test_xtrans().color_at(2, 1))
+		];
+
+		for (cfa, active, corner) in cases.iter() {
+			let (width, height) = (active.x + active.width + 1, active.y + active.height + 2);
+			let (image, border) = crop(sensor(width, height), width, height, *active, cfa.clone(), true, Metadata::test).unwrap();
+			let border = border.unwrap();
+
+			assert_eq!(border.active, *active);
+			assert_eq!(border.raw.data, sensor(width, height));
+			assert_eq!(border.raw.meta.color_at_xy(0, 0), *corner, "{:?} with {:?}", cfa, active);
+
+			// Every photosite has the same color in both
+			for y in 0..active.height {
+				for x in 0..active.width {
+					let raw = border.raw.meta.color_at_xy(x + active.x, y + active.y);
+					assert_eq!(image.meta.color_at_xy(x, y), raw, "{:?} with {:?} at {},{}", cfa, active, x, y);
+					assert_eq!(image.data[(y * active.width + x) as usize], border.raw.data[((y + active.y) * width + x + active.x) as usize]);
+				}
+			}
 		}
 	}
+
+	#[test]
+	fn bad_sizes() {
+		let bad = |data: Vec<u16>, active: Rect| crop(data, 4, 4, active, CFA::RGGB, true, Metadata::test).err();
+
+		assert!(matches!(bad(vec![], active(0, 0, 4, 4)), Some(Error::UnsupportedFormat)));
+		assert!(matches!(bad(sensor(4, 3), active(0, 0, 4, 3)), Some(Error::BadDimensions { len: 12, .. })));
+		assert!(matches!(bad(sensor(4, 4), active(1, 0, 4, 4)), Some(Error::BadDimensions { .. })));
+		assert!(matches!(bad(sensor(4, 4), active(0, 2, 4, 0)), Some(Error::BadDimensions { .. })));
+	}
 }
@@ -1,5 +1,276 @@
-use crate::image::{Attribute ,Color, Image, Sensor, Rgb, Hsv};
-use std::cmp::min;
+use crate::color;
+use crate::color_management::{self, Adaptation, Chromaticity, ColorTransform, Primaries};
+use crate::green;
+use crate::highlights::{self, Highlights};
+use crate::image::{Attribute ,Color, Image, Kind, Metadata, Rect, Sensor, Rgb, Hsv, Hsl, YCbCr, YCbCrMatrix, Xyz, Lab, Lch, Oklab};
+
+/// Where white balance multipliers come from
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum WhiteBalance {
+	/// What the camera chose when the photo was taken
+	AsShot,
+	/// The camera's multipliers for daylight
+	Daylight,
+	/// Red, green, and blue multipliers, used as they are
+	Custom(f32, f32, f32)
+}
+
+impl WhiteBalance {
+	/// The red, green, and blue multipliers. The ones from the metadata are
+	/// normalized so green is 1.0. If the camera didn't record as shot
+	/// multipliers, the daylight ones are used, and if it didn't record those
+	/// either nothing is changed.
+	pub fn multipliers(&self, meta: &Metadata) -> (f32, f32, f32) {
+		match self {
+			WhiteBalance::AsShot => Self::normalize(meta.colordata.cam_mul)
+				.or_else(|| Self::normalize(meta.colordata.pre_mul))
+				.unwrap_or((1.0, 1.0, 1.0)),
+			WhiteBalance::Daylight => Self::normalize(meta.colordata.pre_mul)
+				.unwrap_or((1.0, 1.0, 1.0)),
+			WhiteBalance::Custom(red, green, blue) => (*red, *green, *blue)
+		}
+	}
+
+	// libraw's multipliers are red, green, blue, and the second green. The
+	// second green is often zero when it's the same as the first.
+	fn normalize(mul: [f32; 4]) -> Option<(f32, f32, f32)> {
+		let green = if mul[3] > 0.0 {
+			(mul[1] + mul[3]) / 2.0
+		} else {
+			mul[1]
+		};
+
+		if green <= 0.0 || mul[0] <= 0.0 || mul[2] <= 0.0 {
+			None
+		} else {
+			Some((mul[0] / green, 1.0, mul[2] / green))
+		}
+	}
+}
+
+/// Ways of estimating the white balance from the image itself
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum AutoWhiteBalance {
+	/// Assume the scene averages out to gray
+	GrayWorld,
+	/// Assume the brightest percent of the scene, 0 to 100, is white
+	WhitePatch(f32),
+	/// Assume the edges in the scene average out to gray. Better than
+	/// GrayWorld when there's a large area of one color.
+	GrayEdge
+}
+
+/// Color spaces images can be output in
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ColorSpace {
+	/// sRGB, for the web and most displays
+	Srgb,
+	/// Adobe RGB (1998), for print
+	AdobeRgb,
+	/// Display P3, the sRGB curve with the DCI-P3 primaries and a D65 white
+	DisplayP3,
+	/// ITU-R BT.2020, for HDR and UHD video, with its SDR curve
+	Rec2020,
+	/// ProPhoto RGB, also called ROMM RGB. Wide enough for nearly every
+	/// surface color, and has a D50 white.
+	ProPhoto,
+	/// ACEScg, the linear AP1 space ACES renders in
+	AcesCg
+}
+
+impl ColorSpace {
+	pub fn primaries(&self) -> Primaries {
+		match self {
+			ColorSpace::Srgb => Primaries::SRGB,
+			ColorSpace::AdobeRgb => Primaries {
+				red: (0.64, 0.33),
+				green: (0.21, 0.71),
+				blue: (0.15, 0.06),
+				white: color_management::D65
+			},
+			ColorSpace::DisplayP3 => Primaries {
+				red: (0.680, 0.320),
+				green: (0.265, 0.690),
+				blue: (0.150, 0.060),
+				white: color_management::D65
+			},
+			ColorSpace::Rec2020 => Primaries {
+				red: (0.708, 0.292),
+				green: (0.170, 0.797),
+				blue: (0.131, 0.046),
+				white: color_management::D65
+			},
+			ColorSpace::ProPhoto => Primaries {
+				red: (0.7347, 0.2653),
+				green: (0.1596, 0.8404),
+				blue: (0.0366, 0.0001),
+				white: color_management::D50
+			},
+			ColorSpace::AcesCg => Primaries {
+				red: (0.713, 0.293),
+				green: (0.165, 0.830),
+				blue: (0.128, 0.044),
+				white: (0.32168, 0.33767)
+			}
+		}
+	}
+
+	pub fn white_point(&self) -> Chromaticity {
+		self.primaries().white
+	}
+
+	pub fn transfer(&self) -> Transfer {
+		match self {
+			ColorSpace::Srgb | ColorSpace::DisplayP3 => Transfer::Srgb,
+			ColorSpace::AdobeRgb => Transfer::Gamma(563.0 / 256.0),
+			ColorSpace::Rec2020 => Transfer::Rec2020,
+			ColorSpace::ProPhoto => Transfer::ProPhoto,
+			ColorSpace::AcesCg => Transfer::Linear
+		}
+	}
+}
+
+/// The curves that take linear light to a color space's encoded values.
+/// Negative values get the curve mirrored, like extended sRGB.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Transfer {
+	/// Left linear
+	Linear,
+	/// A plain power curve, encoded as linear to the power of 1/gamma
+	Gamma(f32),
+	/// The sRGB curve, from IEC 61966-2-1
+	/// <https://en.wikipedia.org/wiki/SRGB>
+	Srgb,
+	/// The BT.2020 curve, the same shape as BT.709's with more precise
+	/// constants
+	Rec2020,
+	/// The ProPhoto RGB curve, a 1.8 gamma with a linear toe
+	ProPhoto
+}
+
+// BT.2020's alpha and beta, which meet the two parts of the curve smoothly
+const REC2020_ALPHA: f32 = 1.099_296_8;
+const REC2020_BETA: f32 = 0.018_053_97;
+
+impl Transfer {
+	/// Linear light to encoded
+	pub fn encode(&self, linear: f32) -> f32 {
+		let value = linear.abs();
+		let encoded = match self {
+			Transfer::Linear => value,
+			Transfer::Gamma(gamma) => value.powf(1.0 / gamma),
+			Transfer::Srgb => if value <= 0.0031308 {
+				value * 12.92
+			} else {
+				1.055 * value.powf(1.0 / 2.4) - 0.055
+			},
+			Transfer::Rec2020 => if value < REC2020_BETA {
+				value * 4.5
+			} else {
+				REC2020_ALPHA * value.powf(0.45) - (REC2020_ALPHA - 1.0)
+			},
+			Transfer::ProPhoto => if value < 1.0 / 512.0 {
+				value * 16.0
+			} else {
+				value.powf(1.0 / 1.8)
+			}
+		};
+
+		encoded.copysign(linear)
+	}
+
+	/// Encoded to linear light
+	pub fn decode(&self, encoded: f32) -> f32 {
+		let value = encoded.abs();
+		let linear = match self {
+			Transfer::Linear => value,
+			Transfer::Gamma(gamma) => value.powf(*gamma),
+			Transfer::Srgb => if value <= 0.04045 {
+				value / 12.92
+			} else {
+				((value + 0.055) / 1.055).powf(2.4)
+			},
+			Transfer::Rec2020 => if value < REC2020_BETA * 4.5 {
+				value / 4.5
+			} else {
+				((value + REC2020_ALPHA - 1.0) / REC2020_ALPHA).powf(1.0 / 0.45)
+			},
+			Transfer::ProPhoto => if value < 16.0 / 512.0 {
+				value / 16.0
+			} else {
+				value.powf(1.8)
+			}
+		};
+
+		linear.copysign(encoded)
+	}
+}
+
+/// Image kinds that white balance can be estimated from. They're looked at as
+/// a grid of cells that each have a red, green, and blue value.
+pub trait WhiteBalanceCells: Kind + Sized {
+	/// The cells covering `region` row by row, and how many there are in a row.
+	/// A cell is None if any of its values are at or above `clip`.
+	fn cells(img: &Image<Self, f32>, region: Rect, clip: f32) -> (usize, Vec<Option<[f32; 3]>>);
+}
+
+// Every pixel is a cell
+impl WhiteBalanceCells for Rgb {
+	fn cells(img: &Image<Rgb, f32>, region: Rect, clip: f32) -> (usize, Vec<Option<[f32; 3]>>) {
+		let mut cells = Vec::with_capacity(region.width as usize * region.height as usize);
+
+		for y in region.y..region.y + region.height {
+			for x in region.x..region.x + region.width {
+				let rgb = [
+					img.component(x, y, Color::Red),
+					img.component(x, y, Color::Green),
+					img.component(x, y, Color::Blue)
+				];
+
+				cells.push(if rgb.iter().any(|&c| c >= clip) { None } else { Some(rgb) });
+			}
+		}
+
+		(region.width as usize, cells)
+	}
+}
+
+// Every CFA tile is a cell, with the photosites of each color averaged
+impl WhiteBalanceCells for Sensor {
+	fn cells(img: &Image<Sensor, f32>, region: Rect, clip: f32) -> (usize, Vec<Option<[f32; 3]>>) {
+		let (tile_width, tile_height) = (img.meta.cfa.width(), img.meta.cfa.height());
+		let row_len = (region.width / tile_width) as usize;
+		let rows = (region.height / tile_height) as usize;
+
+		let mut cells = Vec::with_capacity(row_len * rows);
+		for row in 0..rows as u32 {
+			for col in 0..row_len as u32 {
+				let mut sums = [0.0; 3];
+				let mut counts = [0.0; 3];
+				let mut clipped = false;
+
+				for y in region.y + row * tile_height..region.y + (row + 1) * tile_height {
+					for x in region.x + col * tile_width..region.x + (col + 1) * tile_width {
+						let light = img.data[img.meta.xytoi(x, y)];
+						let color = img.meta.color_at_xy(x, y) as usize;
+
+						clipped |= light >= clip;
+						sums[color] += light;
+						counts[color] += 1.0;
+					}
+				}
+
+				cells.push(if clipped || counts.contains(&0.0) {
+					None
+				} else {
+					Some([sums[0] / counts[0], sums[1] / counts[1], sums[2] / counts[2]])
+				});
+			}
+		}
+
+		(row_len, cells)
+	}
+}
 
 pub struct Processor {}
 impl Processor {
@@ -11,6 +282,37 @@ impl Processor {
 		Self::f32clamp(value, 0.0, 1.0)
 	}
 
+	// Linear steps only drop negative values, and only when not scene referred
+	fn linear_floor(meta: &Metadata, value: f32) -> f32 {
+		if meta.scene_referred {
+			value
+		} else {
+			value.max(0.0)
+		}
+	}
+
+	// Color and tone steps clamp to 0.0 through 1.0 when not scene referred
+	fn output_clamp(meta: &Metadata, value: f32) -> f32 {
+		if meta.scene_referred {
+			value
+		} else {
+			Self::normalclamp(value)
+		}
+	}
+
+	// Curves on negative values are mirrored so they stay negative
+	fn signed_powf(value: f32, exponent: f32) -> f32 {
+		value.signum() * value.abs().powf(exponent)
+	}
+
+	/// Clamp to 0.0 through 1.0 for output. Scene referred images aren't
+	/// clamped anywhere else, so this should be done before encoding them.
+	pub fn clamp<K: Kind>(img: &mut Image<K, f32>) {
+		for component in img.data.iter_mut() {
+			*component = Self::normalclamp(*component);
+		}
+	}
+
 	pub fn black_levels(rimg: &mut Image<Sensor, u16>, red: u16, green: u16, blue: u16) {
 		let clamp = |light: u16, color: u16| {
 			if light < color {
@@ -29,18 +331,46 @@ impl Processor {
 			}
 			i += 1;
 		}
+
+		for (level, black) in rimg.meta.clip_levels.iter_mut().zip([red, green, blue].iter()) {
+			*level = (*level - *black as f32).max(0.0);
+		}
 	}
 
+	/// Take away the black levels from the metadata. They're set to zero after,
+	/// and the white level is lowered by the largest of them so it's still
+	/// where every color is saturated.
+	pub fn black_levels_from_meta(rimg: &mut Image<Sensor, u16>) {
+		let [red, green, blue] = rimg.meta.black_levels;
+		Self::black_levels(rimg, red, green, blue);
+
+		rimg.meta.white_level = rimg.meta.white_level.saturating_sub(red.max(green).max(blue));
+		rimg.meta.black_levels = [0; 3];
+	}
+
+	// Exposure, gamma, and white balance don't clip at 1.0 so highlights can
+	// be recovered after. See Metadata::scene_referred for where the rest is
+	// clipped.
+
 	// https://photo.stackexchange.com/a/41936
 	pub fn exposure(rimg: &mut Image<Sensor, f32>, ev: f32) {
+		let scale = 2f32.powf(ev);
 		for light in rimg.data.iter_mut() {
-			*light = Self::normalclamp(*light * 2f32.powf(ev));
+			*light = Self::linear_floor(&rimg.meta, *light * scale);
+		}
+
+		for level in rimg.meta.clip_levels.iter_mut() {
+			*level *= scale;
 		}
 	}
 
 	pub fn gamma(rimg: &mut Image<Sensor, f32>, value: f32) {
 		for light in rimg.data.iter_mut() {
-			*light = Self::normalclamp((*light).powf(1.0/value));
+			*light = Self::signed_powf(Self::linear_floor(&rimg.meta, *light), 1.0/value);
+		}
+
+		for level in rimg.meta.clip_levels.iter_mut() {
+			*level = level.powf(1.0/value);
 		}
 	}
 
@@ -48,18 +378,231 @@ impl Processor {
 		let mut i = 0;
 		for light in rimg.data.iter_mut() {
 			match rimg.meta.color_at_index(i) {
-				Color::Red => *light = Self::normalclamp(*light * red),
-				Color::Green => *light = Self::normalclamp(*light * green),
-				Color::Blue => *light = Self::normalclamp(*light * blue),
+				Color::Red => *light = Self::linear_floor(&rimg.meta, *light * red),
+				Color::Green => *light = Self::linear_floor(&rimg.meta, *light * green),
+				Color::Blue => *light = Self::linear_floor(&rimg.meta, *light * blue),
 			}
 			i += 1;
 		}
+
+		for (level, multiplier) in rimg.meta.clip_levels.iter_mut().zip([red, green, blue].iter()) {
+			*level *= multiplier;
+		}
+	}
+
+	/// Deal with clipped highlights. It goes after black levels and white
+	/// balance, and before demosaicing.
+	pub fn highlights(rimg: &mut Image<Sensor, f32>, mode: Highlights) {
+		match mode {
+			Highlights::Clip => highlights::clip(rimg),
+			Highlights::Blend => highlights::blend(rimg),
+			Highlights::Reconstruct => highlights::reconstruct(rimg)
+		}
+	}
+
+	/// Even out the two greens of a Bayer sensor, the one in the red rows and
+	/// the one in the blue rows, which some sensors read slightly differently.
+	/// Left alone, the difference turns into a maze pattern after demosaicing.
+	/// It's corrected locally where the greens differ by less than
+	/// `threshold`, as a fraction of their level, and larger differences are
+	/// kept as detail. Somewhere around 0.03 to 0.1 works for most cameras.
+	/// Goes before demosaicing, and does nothing for CFAs other than Bayer.
+	pub fn green_equilibration(rimg: &mut Image<Sensor, f32>, threshold: f32) {
+		green::equilibrate(rimg, threshold)
+	}
+
+	/// White balance with multipliers from `wb`
+	pub fn white_balance_from(rimg: &mut Image<Sensor, f32>, wb: WhiteBalance) {
+		let (red, green, blue) = wb.multipliers(&rimg.meta);
+		Self::white_balance(rimg, red, green, blue);
+	}
+
+	/// White balance for light of a correlated color temperature, in kelvin,
+	/// and tint, which is positive towards magenta and negative towards green.
+	/// Returns the multipliers used, or None and leaves the image alone if
+	/// there's no camera matrix in the metadata.
+	pub fn white_balance_temperature(rimg: &mut Image<Sensor, f32>, kelvin: f32, tint: f32) -> Option<(f32, f32, f32)> {
+		let multipliers = Self::temperature_multipliers(&rimg.meta, kelvin, tint)?;
+		Self::white_balance(rimg, multipliers.0, multipliers.1, multipliers.2);
+
+		Some(multipliers)
+	}
+
+	/// The red, green, and blue multipliers, normalized so green is 1.0, that
+	/// make light of the temperature and tint neutral. Temperatures are
+	/// limited to 1000K through 15000K. None if there's no camera matrix in
+	/// the metadata.
+	pub fn temperature_multipliers(meta: &Metadata, kelvin: f32, tint: f32) -> Option<(f32, f32, f32)> {
+		let xyz_to_camera = Self::xyz_to_camera(meta)?;
+
+		let (x, y) = color::temperature_to_xy(kelvin as f64, tint as f64);
+		let camera = color::multiply(&xyz_to_camera, color::xy_to_xyz(x, y));
+
+		if camera.iter().any(|&c| c <= 0.0) {
+			None
+		} else {
+			Some(((camera[1] / camera[0]) as f32, 1.0, (camera[1] / camera[2]) as f32))
+		}
+	}
+
+	/// The correlated color temperature, in kelvin, and tint of the light that
+	/// red, green, and blue multipliers make neutral. It's the inverse of
+	/// [Processor::temperature_multipliers], so this gives the as shot
+	/// temperature from [crate::WhiteBalance::AsShot]'s multipliers. None if
+	/// there's no camera matrix in the metadata.
+	pub fn multipliers_temperature(meta: &Metadata, multipliers: (f32, f32, f32)) -> Option<(f32, f32)> {
+		let (red, green, blue) = multipliers;
+		if red <= 0.0 || green <= 0.0 || blue <= 0.0 {
+			return None;
+		}
+
+		let camera_to_xyz = color::invert(&Self::xyz_to_camera(meta)?)?;
+		let [x, y, z] = color::multiply(
+			&camera_to_xyz,
+			[1.0 / red as f64, 1.0 / green as f64, 1.0 / blue as f64]
+		);
+
+		let sum = x + y + z;
+		if sum <= 0.0 || y <= 0.0 {
+			return None;
+		}
+
+		let (kelvin, tint) = color::xy_to_temperature(x / sum, y / sum);
+		Some((kelvin as f32, tint as f32))
+	}
+
+	// The red, green, and blue rows of the camera matrix. It's None when
+	// libraw doesn't know the camera and leaves it zeroed.
+	pub(crate) fn xyz_to_camera(meta: &Metadata) -> Option<color::Matrix3> {
+		let cam_xyz = &meta.colordata.cam_xyz;
+		let mut matrix = [[0.0; 3]; 3];
+
+		for (row, cam_row) in matrix.iter_mut().zip(cam_xyz.iter()) {
+			for (value, cam_value) in row.iter_mut().zip(cam_row.iter()) {
+				*value = *cam_value as f64;
+			}
+		}
+
+		if matrix.iter().flatten().all(|&v| v == 0.0) {
+			None
+		} else {
+			Some(matrix)
+		}
+	}
+
+	/// Estimate red, green, and blue white balance multipliers, normalized so
+	/// green is 1.0, from the `region` of the image or all of it if that's None.
+	/// Pixels with any value at or above `clip` are clipped and left out, so
+	/// set it to just under whatever white is. If there isn't enough to go
+	/// on, nothing is changed and the multipliers are all 1.0.
+	pub fn auto_white_balance<K: WhiteBalanceCells>(img: &Image<K, f32>, method: AutoWhiteBalance, region: Option<Rect>, clip: f32) -> (f32, f32, f32) {
+		let bounds = Rect { x: 0, y: 0, width: img.meta.width, height: img.meta.height };
+		let region = match region {
+			Some(region) => {
+				let x = region.x.min(bounds.width);
+				let y = region.y.min(bounds.height);
+
+				Rect {
+					x,
+					y,
+					width: region.width.min(bounds.width - x),
+					height: region.height.min(bounds.height - y)
+				}
+			},
+			None => bounds
+		};
+
+		let (row_len, cells) = K::cells(img, region, clip);
+
+		let gray = match method {
+			AutoWhiteBalance::GrayWorld => Self::cell_mean(cells.iter().flatten()),
+			AutoWhiteBalance::WhitePatch(percent) => {
+				let mut bright: Vec<[f32; 3]> = cells.iter().flatten().copied().collect();
+				bright.sort_by(|a, b| {
+					let (a, b) = (a[0] + a[1] + a[2], b[0] + b[1] + b[2]);
+					b.partial_cmp(&a).unwrap_or(std::cmp::Ordering::Equal)
+				});
+
+				let count = (bright.len() as f32 * percent / 100.0).ceil().max(1.0) as usize;
+				Self::cell_mean(bright.iter().take(count))
+			},
+			AutoWhiteBalance::GrayEdge => Self::edge_mean(row_len, &cells)
+		};
+
+		match gray {
+			Some([red, green, blue]) if red > 0.0 && green > 0.0 && blue > 0.0 => {
+				(green / red, 1.0, green / blue)
+			},
+			_ => (1.0, 1.0, 1.0)
+		}
+	}
+
+	fn cell_mean<'a, I: Iterator<Item = &'a [f32; 3]>>(cells: I) -> Option<[f32; 3]> {
+		let mut sum = [0.0; 3];
+		let mut count = 0;
+
+		for cell in cells {
+			sum[0] += cell[0];
+			sum[1] += cell[1];
+			sum[2] += cell[2];
+			count += 1;
+		}
+
+		if count == 0 {
+			None
+		} else {
+			Some([sum[0] / count as f32, sum[1] / count as f32, sum[2] / count as f32])
+		}
+	}
+
+	// The mean gradient magnitude of each channel. The cells are smoothed
+	// with their neighbours first so noise doesn't count as edges.
+	fn edge_mean(row_len: usize, cells: &[Option<[f32; 3]>]) -> Option<[f32; 3]> {
+		if row_len == 0 {
+			return None;
+		}
+
+		let rows = cells.len() / row_len;
+		let at = |x: usize, y: usize| cells[y * row_len + x];
+
+		let smooth: Vec<Option<[f32; 3]>> = (0..cells.len()).map(|i| {
+			let (x, y) = (i % row_len, i / row_len);
+			at(x, y)?;
+
+			let neighbours = (y.saturating_sub(1)..(y + 2).min(rows))
+				.flat_map(|ny| (x.saturating_sub(1)..(x + 2).min(row_len)).map(move |nx| (nx, ny)))
+				.filter_map(|(nx, ny)| at(nx, ny))
+				.collect::<Vec<[f32; 3]>>();
+
+			Self::cell_mean(neighbours.iter())
+		}).collect();
+
+		let mut edges = vec![];
+		for y in 0..rows.saturating_sub(1) {
+			for x in 0..row_len - 1 {
+				let here = smooth[y * row_len + x];
+				let right = smooth[y * row_len + x + 1];
+				let below = smooth[(y + 1) * row_len + x];
+
+				if let (Some(here), Some(right), Some(below)) = (here, right, below) {
+					let gradient = |c: usize| (right[c] - here[c]).hypot(below[c] - here[c]);
+					edges.push([gradient(0), gradient(1), gradient(2)]);
+				}
+			}
+		}
+
+		Self::cell_mean(edges.iter())
 	}
 
 	// https://math.stackexchange.com/a/906280
 	pub fn brightness(cimg: &mut Image<Hsv, f32>, value: f32) {
+		let scene_referred = cimg.meta.scene_referred;
 		for comp in cimg.component_iter_mut(Attribute::Value) {
-			*comp = Self::normalclamp(*comp + value);
+			*comp = if scene_referred {
+				*comp + value
+			} else {
+				Self::normalclamp(*comp + value)
+			};
 		}
 	}
 
@@ -71,33 +614,65 @@ impl Processor {
 	}
 
 	pub fn saturation(img: &mut Image<Hsv, f32>, scalar: f32) {
+		// Saturation can't be negative, but above 1.0 is out of gamut
+		// colors which scene referred images can keep
+		let scene_referred = img.meta.scene_referred;
 		for saturation in img.component_iter_mut(Attribute::Saturation) {
-			*saturation =  Self::normalclamp(*saturation * scalar);
+			*saturation = if scene_referred {
+				(*saturation * scalar).max(0.0)
+			} else {
+				Self::normalclamp(*saturation * scalar)
+			};
 		}
 	}
 
+	/// Camera RGB to linear sRGB, using the camera matrix in the metadata.
+	/// If there isn't one the data is taken to already be sRGB.
 	#[allow(non_snake_case)]
 	pub fn to_sRGB(cimg: &mut Image<Rgb, f32>) {
-		let mat = cimg.meta.colordata.rgb_cam;
+		Self::to_color_space(cimg, ColorSpace::Srgb);
+	}
+
+	/// Camera RGB to the linear RGB of `space`, using the camera matrix in
+	/// the metadata and Bradford adaptation to the space's white. If there's
+	/// no camera matrix the data is taken to already be sRGB.
+	pub fn to_color_space(cimg: &mut Image<Rgb, f32>, space: ColorSpace) {
+		let primaries = space.primaries();
+		let transform = ColorTransform::new(&cimg.meta, &primaries, Adaptation::Bradford)
+			.or_else(|| ColorTransform::from_camera_to_xyz(Primaries::SRGB.to_xyz(), Primaries::SRGB.white, &primaries, Adaptation::Bradford));
+
+		if let Some(transform) = transform {
+			Self::convert_color(cimg, &transform);
+			cimg.meta.primaries = primaries;
+		}
+	}
+
+	/// Take white balanced camera RGB to the linear RGB of the transform's
+	/// output color space. The primaries in the metadata are left alone, so
+	/// set them to the output's for conversions to Xyz and the other kinds.
+	pub fn convert_color(cimg: &mut Image<Rgb, f32>, transform: &ColorTransform) {
+		let mat = transform.matrix();
 		for pix in cimg.pixel_index_range() {
-			let (r, g, b) = (cimg.data[pix], cimg.data[pix+1], cimg.data[pix+2]);
+			let rgb = [cimg.data[pix] as f64, cimg.data[pix+1] as f64, cimg.data[pix+2] as f64];
+			let [r, g, b] = color::multiply(&mat, rgb);
 
-			cimg.data[pix] = Self::normalclamp(mat[0][0] * r + mat[0][1] * g + mat[0][2] * b);   // X
-			cimg.data[pix+1] = Self::normalclamp(mat[1][0] * r + mat[1][1] * g + mat[1][2] * b); // Y
-			cimg.data[pix+2] = Self::normalclamp(mat[2][0] * r + mat[2][1] * g + mat[2][2] * b); // Z
+			cimg.data[pix] = Self::output_clamp(&cimg.meta, r as f32);
+			cimg.data[pix+1] = Self::output_clamp(&cimg.meta, g as f32);
+			cimg.data[pix+2] = Self::output_clamp(&cimg.meta, b as f32);
 		}
 	}
 
 	#[allow(non_snake_case)]
 	pub fn sRGB_gamma(cimg: &mut Image<Rgb, f32>) {
+		Self::color_space_gamma(cimg, ColorSpace::Srgb);
+	}
+
+	/// Encode linear RGB with the transfer function of `space`
+	pub fn color_space_gamma(cimg: &mut Image<Rgb, f32>, space: ColorSpace) {
+		let transfer = space.transfer();
+		let meta = &cimg.meta;
 		for component in cimg.data.iter_mut() {
-			// Value taken from Wikipedia page on sRGB
-			// https://en.wikipedia.org/wiki/SRGB
-			if *component <= 0.0031308 {
-				*component = Self::f32clamp(*component * 12.92, 0.0, 1.0);
-			} else {
-				*component = Self::f32clamp(1.055 * component.powf(1.0/2.4) - 0.055, 0.0, 1.0);
-			}
+			*component = Self::output_clamp(meta, transfer.encode(*component));
 		}
 	}
 
@@ -195,11 +770,176 @@ impl Processor {
 			hue, saturation, value);
 		}
 	}
+
+	// https://en.wikipedia.org/wiki/HSL_and_HSV#From_RGB
+	pub fn rgb_to_hsl(mut rgb: Image<Rgb, f32>) -> Image<Hsl, f32> {
+		for pix in rgb.pixel_index_range() {
+			let (h, s, l) = Processor::pixel_rgb_to_hsl(rgb.data[pix], rgb.data[pix+1], rgb.data[pix+2]);
+
+			rgb.data[pix] = h;
+			rgb.data[pix+1] = s;
+			rgb.data[pix+2] = l;
+		}
+
+		Image {
+			data: rgb.data,
+			kind: Hsl {},
+			meta: rgb.meta
+		}
+	}
+
+	// Hue is the same as HSV's
+	fn pixel_rgb_to_hsl(r: f32, g: f32, b: f32) -> (f32, f32, f32) {
+		let (hue, _, value) = Self::pixel_rgb_to_hsv(r, g, b);
+		let x_min = r.min(g.min(b));
+		let lightness = (value + x_min) / 2.0;
+
+		let lightness_saturation = if lightness <= 0.0 || lightness >= 1.0 {
+			0.0
+		} else {
+			(value - x_min) / (1.0 - (2.0 * lightness - 1.0).abs())
+		};
+
+		(hue, lightness_saturation, lightness)
+	}
+
+	// https://en.wikipedia.org/wiki/HSL_and_HSV#HSL_to_HSV
+	pub fn hsl_to_rgb(mut hsl: Image<Hsl, f32>) -> Image<Rgb, f32> {
+		for pix in hsl.pixel_index_range() {
+			let (r, g, b) = Processor::pixel_hsl_to_rgb(hsl.data[pix], hsl.data[pix+1], hsl.data[pix+2]);
+
+			hsl.data[pix] = r;
+			hsl.data[pix+1] = g;
+			hsl.data[pix+2] = b;
+		}
+
+		Image {
+			data: hsl.data,
+			kind: Rgb {},
+			meta: hsl.meta
+		}
+	}
+
+	fn pixel_hsl_to_rgb(hue: f32, saturation: f32, lightness: f32) -> (f32, f32, f32) {
+		let value = lightness + saturation * lightness.min(1.0 - lightness);
+		let value_saturation = if value == 0.0 {
+			0.0
+		} else {
+			2.0 * (1.0 - lightness / value)
+		};
+
+		Self::pixel_hsv_to_rgb(hue, value_saturation, value)
+	}
+
+	/// RGB to YCbCr with the coefficients of `matrix`
+	pub fn rgb_to_ycbcr(mut rgb: Image<Rgb, f32>, matrix: YCbCrMatrix) -> Image<YCbCr, f32> {
+		let (kr, kb) = matrix.coefficients();
+		for pix in rgb.pixel_index_range() {
+			let (r, g, b) = (rgb.data[pix], rgb.data[pix+1], rgb.data[pix+2]);
+			let y = kr * r + (1.0 - kr - kb) * g + kb * b;
+
+			rgb.data[pix] = y;
+			rgb.data[pix+1] = (b - y) / (2.0 * (1.0 - kb));
+			rgb.data[pix+2] = (r - y) / (2.0 * (1.0 - kr));
+		}
+
+		Image {
+			data: rgb.data,
+			kind: YCbCr { matrix },
+			meta: rgb.meta
+		}
+	}
+
+	pub fn ycbcr_to_rgb(mut ycbcr: Image<YCbCr, f32>) -> Image<Rgb, f32> {
+		let (kr, kb) = ycbcr.kind.matrix.coefficients();
+		for pix in ycbcr.pixel_index_range() {
+			let (y, cb, cr) = (ycbcr.data[pix], ycbcr.data[pix+1], ycbcr.data[pix+2]);
+			let r = y + 2.0 * (1.0 - kr) * cr;
+			let b = y + 2.0 * (1.0 - kb) * cb;
+
+			ycbcr.data[pix] = r;
+			ycbcr.data[pix+1] = (y - kr * r - kb * b) / (1.0 - kr - kb);
+			ycbcr.data[pix+2] = b;
+		}
+
+		Image {
+			data: ycbcr.data,
+			kind: Rgb {},
+			meta: ycbcr.meta
+		}
+	}
+
+	/// Linear RGB to XYZ, with the primaries in the metadata
+	pub fn rgb_to_xyz(rgb: Image<Rgb, f32>) -> Image<Xyz, f32> {
+		let to_xyz = rgb.meta.primaries.to_xyz();
+		Self::convert_pixels(rgb, Xyz, |rgb| color::multiply(&to_xyz, rgb))
+	}
+
+	/// XYZ to linear RGB, with the primaries in the metadata. Nothing is
+	/// clamped, so colors outside them go negative or above 1.0. Panics if
+	/// the primaries can't be inverted, like when two of them are the same.
+	pub fn xyz_to_rgb(xyz: Image<Xyz, f32>) -> Image<Rgb, f32> {
+		let from_xyz = xyz.meta.primaries.from_xyz()
+			.expect("The primaries in the metadata don't make an invertible matrix");
+		Self::convert_pixels(xyz, Rgb, |xyz| color::multiply(&from_xyz, xyz))
+	}
+
+	pub fn xyz_to_lab(xyz: Image<Xyz, f32>) -> Image<Lab, f32> {
+		let (x, y) = xyz.meta.white_point();
+		let white = color::xy_to_xyz(x, y);
+		Self::convert_pixels(xyz, Lab, |xyz| color::xyz_to_lab(xyz, white))
+	}
+
+	pub fn lab_to_xyz(lab: Image<Lab, f32>) -> Image<Xyz, f32> {
+		let (x, y) = lab.meta.white_point();
+		let white = color::xy_to_xyz(x, y);
+		Self::convert_pixels(lab, Xyz, |lab| color::lab_to_xyz(lab, white))
+	}
+
+	pub fn lab_to_lch(lab: Image<Lab, f32>) -> Image<Lch, f32> {
+		Self::convert_pixels(lab, Lch, color::lab_to_lch)
+	}
+
+	pub fn lch_to_lab(lch: Image<Lch, f32>) -> Image<Lab, f32> {
+		Self::convert_pixels(lch, Lab, color::lch_to_lab)
+	}
+
+	/// XYZ to Oklab. It's made for a D65 white, so the metadata's is adapted
+	/// to that first with Bradford.
+	pub fn xyz_to_oklab(xyz: Image<Xyz, f32>) -> Image<Oklab, f32> {
+		let adaptation = Adaptation::Bradford.matrix(xyz.meta.white_point(), color_management::D65);
+		Self::convert_pixels(xyz, Oklab, |xyz| color::xyz_to_oklab(color::multiply(&adaptation, xyz)))
+	}
+
+	pub fn oklab_to_xyz(oklab: Image<Oklab, f32>) -> Image<Xyz, f32> {
+		let adaptation = Adaptation::Bradford.matrix(color_management::D65, oklab.meta.white_point());
+		Self::convert_pixels(oklab, Xyz, |lab| color::multiply(&adaptation, color::oklab_to_xyz(lab)))
+	}
+
+	// Every three components through `f`, done in f64, into an image of
+	// another kind
+	fn convert_pixels<A: Kind, B: Kind, F>(mut img: Image<A, f32>, kind: B, f: F) -> Image<B, f32>
+	where F: Fn([f64; 3]) -> [f64; 3]
+	{
+		for pixel in img.data.chunks_mut(3) {
+			let converted = f([pixel[0] as f64, pixel[1] as f64, pixel[2] as f64]);
+			for (component, value) in pixel.iter_mut().zip(converted.iter()) {
+				*component = *value as f32;
+			}
+		}
+
+		Image {
+			kind,
+			data: img.data,
+			meta: img.meta
+		}
+	}
 }
 
 #[cfg(test)]
 mod cfa_tets {
 	use super::*;
+	use crate::test_util::{assert_close, rgb_image};
 
 	// Simple colors. Maxed Red, Green, and Blue
 	#[test]
@@ -291,4 +1031,483 @@ mod cfa_tets {
 			(0.0, 0.0, 1.0)
 		);
 	}
+
+	#[test]
+	fn white_balance_normalize() {
+		// Green is 1.0, and the two greens are averaged
+		assert_eq!(WhiteBalance::normalize([2.0, 1.0, 1.5, 1.0]), Some((2.0, 1.0, 1.5)));
+		assert_eq!(WhiteBalance::normalize([1024.0, 512.0, 768.0, 0.0]), Some((2.0, 1.0, 1.5)));
+		assert_eq!(WhiteBalance::normalize([2.0, 0.8, 1.5, 1.2]), Some((2.0, 1.0, 1.5)));
+
+		// Not recorded
+		assert_eq!(WhiteBalance::normalize([0.0, 0.0, 0.0, 0.0]), None);
+	}
+
+	#[test]
+	fn temperature_round_trip() {
+		use crate::image::CFA;
+
+		let meta = Metadata::test_camera(2, 2, CFA::RGGB);
+		for &(kelvin, tint) in [(2500.0, 0.0), (3200.0, 10.0), (5000.0, -20.0), (6500.0, 0.0), (10000.0, 5.0)].iter() {
+			let multipliers = Processor::temperature_multipliers(&meta, kelvin, tint).unwrap();
+			assert_eq!(multipliers.1, 1.0);
+
+			let (back_kelvin, back_tint) = Processor::multipliers_temperature(&meta, multipliers).unwrap();
+			assert_close(back_kelvin / kelvin, 1.0, 1e-3);
+			assert_close(back_tint, tint, 0.1);
+		}
+
+		// Warmer light needs less red and more blue
+		let warm = Processor::temperature_multipliers(&meta, 3000.0, 0.0).unwrap();
+		let cool = Processor::temperature_multipliers(&meta, 7000.0, 0.0).unwrap();
+		assert!(warm.0 < cool.0 && warm.2 > cool.2);
+
+		// Without a camera matrix there's no telling
+		let meta = Metadata::test(2, 2, CFA::RGGB);
+		assert_eq!(Processor::temperature_multipliers(&meta, 5000.0, 0.0), None);
+		assert_eq!(Processor::multipliers_temperature(&meta, (2.0, 1.0, 1.5)), None);
+	}
+
+	#[test]
+	fn as_shot_temperature() {
+		use crate::image::CFA;
+
+		// As shot multipliers the camera would record under CIE illuminant A,
+		// a 2856K tungsten light on the locus, and D65, daylight at 6504K and
+		// a little green of it
+		for &(x, y, kelvin, tint) in [(0.44757, 0.40745, 2856.0, 0.0), (0.31271, 0.32902, 6504.0, -9.6)].iter() {
+			let mut meta = Metadata::test_camera(2, 2, CFA::RGGB);
+			let camera = color::multiply(&Processor::xyz_to_camera(&meta).unwrap(), color::xy_to_xyz(x, y));
+			meta.colordata.cam_mul = [camera[1] / camera[0], 1.0, camera[1] / camera[2], 0.0].map(|m| m as f32);
+
+			let as_shot = WhiteBalance::AsShot.multipliers(&meta);
+			let (shot_kelvin, shot_tint) = Processor::multipliers_temperature(&meta, as_shot).unwrap();
+			assert_close(shot_kelvin, kelvin, 20.0);
+			assert_close(shot_tint, tint, 1.0);
+		}
+	}
+
+	// A camera that sees red at half and blue at 0.8 of green, which white
+	// balance multipliers of 2.0 and 1.25 undo
+	const CAST: [f32; 3] = [0.5, 1.0, 0.8];
+	const CAST_MULTIPLIERS: (f32, f32, f32) = (2.0, 1.0, 1.25);
+
+	// An RGB image of `scene` as the camera saw it
+	fn cast_image(width: u32, height: u32, scene: impl Fn(u32, u32) -> [f32; 3]) -> Image<Rgb, f32> {
+		use crate::image::CFA;
+
+		let data = (0..width * height)
+			.flat_map(|i| {
+				let rgb = scene(i % width, i / width);
+				vec![rgb[0] * CAST[0], rgb[1] * CAST[1], rgb[2] * CAST[2]]
+			})
+			.collect();
+
+		Image { kind: Rgb, data, meta: Metadata::test(width, height, CFA::RGGB) }
+	}
+
+	#[test]
+	fn auto_white_balance_gray_world() {
+		// Grays of every brightness, and a clipped red that would pull it off
+		let img = cast_image(8, 8, |x, y| match (x, y) {
+			(0, 0) => [2.0, 0.2, 0.2],
+			_ => [(x + y) as f32 / 16.0 + 0.05; 3]
+		});
+		let multipliers = Processor::auto_white_balance(&img, AutoWhiteBalance::GrayWorld, None, 0.99);
+		assert_close(multipliers, CAST_MULTIPLIERS, 1e-5);
+
+		// Everything clipped leaves nothing to go on
+		let clipped = Processor::auto_white_balance(&img, AutoWhiteBalance::GrayWorld, None, 0.0);
+		assert_eq!(clipped, (1.0, 1.0, 1.0));
+	}
+
+	#[test]
+	fn auto_white_balance_white_patch() {
+		// A dark red scene with a white patch that's the brightest 10%, and a
+		// clipped highlight brighter than that
+		let scene = |x: u32, y: u32| match (x, y) {
+			(9, 9) => [1.5, 1.5, 1.0],
+			(_, 0) => [0.9; 3],
+			_ => [0.3, 0.1, 0.1]
+		};
+		let img = cast_image(10, 10, scene);
+
+		let multipliers = Processor::auto_white_balance(&img, AutoWhiteBalance::WhitePatch(10.0), None, 0.99);
+		assert_close(multipliers, CAST_MULTIPLIERS, 1e-5);
+
+		// Gray world takes the red for the color of the light
+		let (red, _, _) = Processor::auto_white_balance(&img, AutoWhiteBalance::GrayWorld, None, 0.99);
+		assert!(red < 1.5, "gray world red was {}", red);
+	}
+
+	#[test]
+	fn auto_white_balance_gray_edge() {
+		// A big flat green area with neutral stripes on it. Only the stripes
+		// have edges.
+		let img = cast_image(12, 12, |x, _| {
+			let stripe = if x % 4 < 2 { 0.3 } else { 0.0 };
+			[0.1 + stripe, 0.6 + stripe, 0.2 + stripe]
+		});
+
+		let multipliers = Processor::auto_white_balance(&img, AutoWhiteBalance::GrayEdge, None, 0.99);
+		assert_close(multipliers, CAST_MULTIPLIERS, 1e-4);
+
+		let (red, _, _) = Processor::auto_white_balance(&img, AutoWhiteBalance::GrayWorld, None, 0.99);
+		assert!(red > 3.0, "gray world red was {}", red);
+	}
+
+	#[test]
+	fn auto_white_balance_region() {
+		// Gray on the right, red on the left
+		let img = cast_image(10, 6, |x, _| if x >= 7 { [0.4; 3] } else { [0.4, 0.1, 0.1] });
+
+		let right = Rect { x: 7, y: 1, width: 3, height: 4 };
+		let multipliers = Processor::auto_white_balance(&img, AutoWhiteBalance::GrayWorld, Some(right), 0.99);
+		assert_close(multipliers, CAST_MULTIPLIERS, 1e-5);
+
+		// Running off the image is cut back to it
+		let past = Rect { x: 8, y: 2, width: 100, height: 100 };
+		let multipliers = Processor::auto_white_balance(&img, AutoWhiteBalance::GrayWorld, Some(past), 0.99);
+		assert_close(multipliers, CAST_MULTIPLIERS, 1e-5);
+
+		// And entirely off it there's nothing
+		let outside = Rect { x: 20, y: 0, width: 4, height: 4 };
+		let multipliers = Processor::auto_white_balance(&img, AutoWhiteBalance::GrayWorld, Some(outside), 0.99);
+		assert_eq!(multipliers, (1.0, 1.0, 1.0));
+	}
+
+	#[test]
+	fn auto_white_balance_sensor() {
+		use crate::image::CFA;
+
+		// The gray world image as RGGB photosites, which are averaged per tile
+		let rgb = cast_image(8, 8, |x, y| [(x / 2 + y / 2) as f32 / 8.0 + 0.05; 3]);
+		let meta = Metadata::test(8, 8, CFA::RGGB);
+		let mut data: Vec<f32> = (0..64)
+			.map(|i| {
+				let (x, y) = meta.itoxy(i);
+				rgb.component(x, y, meta.color_at_xy(x, y))
+			})
+			.collect();
+
+		// One clipped photosite takes its whole tile out, which is off color
+		data[meta.xytoi(6, 6)] = 1.0;
+		data[meta.xytoi(7, 7)] = 0.01;
+		let mut sensor = Image { kind: Sensor, data, meta };
+
+		let multipliers = Processor::auto_white_balance(&sensor, AutoWhiteBalance::GrayWorld, None, 0.99);
+		assert_close(multipliers, CAST_MULTIPLIERS, 1e-5);
+
+		// Tiles that don't fit in the region are left out, here the bottom row
+		// and right column of photosites
+		let partial = Rect { x: 2, y: 2, width: 5, height: 3 };
+		let i = sensor.meta.xytoi(6, 2);
+		sensor.data[i] = 0.9;
+		let multipliers = Processor::auto_white_balance(&sensor, AutoWhiteBalance::GrayWorld, Some(partial), 0.99);
+		assert_close(multipliers, CAST_MULTIPLIERS, 1e-5);
+	}
+
+	#[test]
+	fn black_levels_from_meta() {
+		use crate::image::CFA;
+
+		let mut meta = Metadata::test(2, 2, CFA::RGGB);
+		meta.white_level = 1000;
+		meta.clip_levels = [1000.0; 3];
+		meta.black_levels = [100, 110, 120];
+		let mut rimg = Image { kind: Sensor, data: vec![550, 110, 50, 1000], meta };
+
+		Processor::black_levels_from_meta(&mut rimg);
+		assert_eq!(rimg.data, vec![450, 0, 0, 880]);
+		assert_eq!(rimg.meta.black_levels, [0; 3]);
+
+		// Saturated is still saturated for every color
+		assert_eq!(rimg.meta.white_level, 880);
+		assert_eq!(rimg.meta.clip_levels, [900.0, 890.0, 880.0]);
+	}
+
+	fn scene_sensor(scene_referred: bool, data: Vec<f32>) -> Image<Sensor, f32> {
+		use crate::image::CFA;
+
+		let mut meta = Metadata::test(2, 2, CFA::RGGB);
+		meta.clip_levels = [1.0; 3];
+		meta.scene_referred = scene_referred;
+		Image { kind: Sensor, data, meta }
+	}
+
+	fn scene_rgb(scene_referred: bool, data: Vec<f32>) -> Image<Rgb, f32> {
+		let mut img = rgb_image(data, Primaries::SRGB);
+		img.meta.scene_referred = scene_referred;
+		img
+	}
+
+	#[test]
+	fn exposure_round_trip() {
+		let data = vec![0.1, 0.5, 0.9, -0.2];
+
+		let mut rimg = scene_sensor(true, data.clone());
+		Processor::exposure(&mut rimg, 2.0);
+		assert_close(&rimg.data[..], &[0.4, 2.0, 3.6, -0.8][..], 1e-6);
+		Processor::exposure(&mut rimg, -2.0);
+		assert_close(&rimg.data[..], &data[..], 1e-6);
+		assert_eq!(rimg.meta.clip_levels, [1.0; 3]);
+
+		// Above 1.0 comes back either way, but below black is dropped when
+		// not scene referred
+		let mut rimg = scene_sensor(false, data);
+		Processor::exposure(&mut rimg, 2.0);
+		Processor::exposure(&mut rimg, -2.0);
+		assert_close(&rimg.data[..], &[0.1, 0.5, 0.9, 0.0][..], 1e-6);
+	}
+
+	#[test]
+	fn scene_referred_range() {
+		let mut rimg = scene_sensor(true, vec![0.6, -0.1, 0.2, 0.4]);
+		Processor::white_balance(&mut rimg, 2.0, 1.0, 0.5);
+		assert_close(&rimg.data[..], &[1.2, -0.1, 0.2, 0.2][..], 1e-6);
+
+		// No camera matrix, so it's sRGB to sRGB
+		let mut rgb = scene_rgb(true, vec![1.5, -0.25, 0.5]);
+		Processor::to_sRGB(&mut rgb);
+		assert_close(&rgb.data[..], &[1.5, -0.25, 0.5][..], 1e-5);
+
+		// Saturation 0.8 and value 0.9
+		let mut hsv = Processor::rgb_to_hsv(scene_rgb(true, vec![0.9, 0.18, 0.18]));
+		Processor::saturation(&mut hsv, 2.0);
+		assert_close(hsv.data[1], 1.6, 1e-5);
+		Processor::brightness(&mut hsv, 0.5);
+		assert_close(hsv.data[2], 1.4, 1e-5);
+		Processor::brightness(&mut hsv, -1.6);
+		assert_close(hsv.data[2], -0.2, 1e-5);
+
+		// Only clamping gets them back in range
+		let mut rgb = Processor::hsv_to_rgb(hsv);
+		rgb.data.extend_from_slice(&[1.5, -0.25, 0.5]);
+		assert!(rgb.data.iter().any(|&c| c < 0.0));
+		assert!(rgb.data.iter().any(|&c| c > 1.0));
+		Processor::clamp(&mut rgb);
+		assert_close(&rgb.data[3..], &[1.0, 0.0, 0.5][..], 0.0);
+		assert!(rgb.data.iter().all(|&c| (0.0..=1.0).contains(&c)));
+	}
+
+	#[test]
+	fn display_referred_range() {
+		// Linear steps only drop negatives
+		let mut rimg = scene_sensor(false, vec![0.6, -0.1, 0.2, 0.4]);
+		Processor::white_balance(&mut rimg, 2.0, 1.0, 0.5);
+		assert_close(&rimg.data[..], &[1.2, 0.0, 0.2, 0.2][..], 1e-6);
+
+		// Color and tone steps clamp to 0.0 through 1.0
+		let mut rgb = scene_rgb(false, vec![1.5, -0.25, 0.5]);
+		Processor::to_sRGB(&mut rgb);
+		assert_close(&rgb.data[..], &[1.0, 0.0, 0.5][..], 1e-5);
+
+		let mut hsv = Processor::rgb_to_hsv(scene_rgb(false, vec![0.9, 0.18, 0.18]));
+		Processor::saturation(&mut hsv, 2.0);
+		assert_eq!(hsv.data[1], 1.0);
+		Processor::brightness(&mut hsv, 0.5);
+		assert_eq!(hsv.data[2], 1.0);
+		Processor::brightness(&mut hsv, -1.6);
+		assert_eq!(hsv.data[2], 0.0);
+	}
+
+	#[test]
+	fn green_equilibration() {
+		use crate::image::CFA;
+
+		// A flat gray where the greens in the red rows read 2% high and the
+		// ones in the blue rows 2% low
+		let (width, height) = (8, 8);
+		let imbalanced = || {
+			let meta = Metadata::test(width, height, CFA::RGGB);
+			let data = (0..(width * height) as usize).map(|i| {
+				match (meta.color_at_index(i), meta.itoxy(i).1 % 2) {
+					(Color::Green, 0) => 0.51,
+					(Color::Green, _) => 0.49,
+					_ => 0.5
+				}
+			}).collect();
+
+			Image { kind: Sensor {}, data, meta }
+		};
+
+		let mut even = imbalanced();
+		Processor::green_equilibration(&mut even, 0.05);
+		for (i, light) in even.data.iter().enumerate() {
+			assert!((light - 0.5).abs() < 1e-6, "{} at {:?}", light, even.meta.itoxy(i));
+		}
+
+		// Over the threshold it's taken to be detail
+		let mut kept = imbalanced();
+		Processor::green_equilibration(&mut kept, 0.01);
+		assert!(kept.data == imbalanced().data);
+	}
+
+	#[test]
+	fn color_space_primaries() {
+		fn assert_matrix_close(space: ColorSpace, expected: color::Matrix3) {
+			let matrix = space.primaries().to_xyz();
+			assert_close(matrix, expected, 2e-4);
+
+			// And back again
+			let back = color::compose(&space.primaries().from_xyz().unwrap(), &matrix);
+			assert_close(back, [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]], 1e-9);
+		}
+
+		// RGB to XYZ. Adobe RGB and ProPhoto are Bruce Lindbloom's, Display P3
+		// Apple's, Rec. 2020 from ITU-R BT.2087, and ACEScg from ACES
+		// TB-2014-004.
+		assert_matrix_close(ColorSpace::Srgb, [
+			[0.4124564, 0.3575761, 0.1804375],
+			[0.2126729, 0.7151522, 0.0721750],
+			[0.0193339, 0.1191920, 0.9503041]
+		]);
+		assert_matrix_close(ColorSpace::AdobeRgb, [
+			[0.5767309, 0.1855540, 0.1881852],
+			[0.2973769, 0.6273491, 0.0752741],
+			[0.0270343, 0.0706872, 0.9911085]
+		]);
+		assert_matrix_close(ColorSpace::DisplayP3, [
+			[0.4865709, 0.2656677, 0.1982173],
+			[0.2289746, 0.6917385, 0.0792869],
+			[0.0000000, 0.0451134, 1.0439444]
+		]);
+		assert_matrix_close(ColorSpace::Rec2020, [
+			[0.6369580, 0.1446169, 0.1688810],
+			[0.2627002, 0.6779981, 0.0593017],
+			[0.0000000, 0.0280727, 1.0609851]
+		]);
+		assert_matrix_close(ColorSpace::ProPhoto, [
+			[0.7976749, 0.1351917, 0.0313534],
+			[0.2880402, 0.7118741, 0.0000857],
+			[0.0000000, 0.0000000, 0.8252100]
+		]);
+		assert_matrix_close(ColorSpace::AcesCg, [
+			[0.6624542, 0.1340042, 0.1561877],
+			[0.2722287, 0.6740818, 0.0536895],
+			[-0.0055746, 0.0040607, 1.0103391]
+		]);
+	}
+
+	#[test]
+	fn color_space_transfer() {
+		// Linear 0.5 and 0.01, encoded
+		let published = [
+			(ColorSpace::Srgb, 0.735357, 0.099853),
+			(ColorSpace::AdobeRgb, 0.729658, 0.123193),
+			(ColorSpace::DisplayP3, 0.735357, 0.099853),
+			(ColorSpace::Rec2020, 0.705436, 0.045),
+			(ColorSpace::ProPhoto, 0.680395, 0.077426),
+			(ColorSpace::AcesCg, 0.5, 0.01)
+		];
+
+		for &(space, half, dark) in published.iter() {
+			let transfer = space.transfer();
+			assert!((transfer.encode(0.5) - half).abs() < 1e-5, "{:?} encoded 0.5 to {}", space, transfer.encode(0.5));
+			assert!((transfer.encode(0.01) - dark).abs() < 1e-5, "{:?} encoded 0.01 to {}", space, transfer.encode(0.01));
+
+			for &linear in [-0.25, 0.0, 0.001, 0.01, 0.018, 0.2, 0.5, 1.0, 1.5].iter() {
+				let back = transfer.decode(transfer.encode(linear));
+				assert!((back - linear).abs() < 1e-5, "{:?} took {} to {}", space, linear, back);
+			}
+		}
+	}
+
+	#[test]
+	fn perceptual_kinds() {
+		let colors = vec![1.0, 1.0, 1.0, 0.5, 0.5, 0.5, 0.8, 0.2, 0.1, 0.05, 0.4, 0.9];
+
+		// White and grays are neutral in every white point
+		for &primaries in [Primaries::SRGB, ColorSpace::ProPhoto.primaries(), ColorSpace::AcesCg.primaries()].iter() {
+			let lab: Image<Lab, f32> = rgb_image(colors.clone(), primaries).into();
+			let oklab: Image<Oklab, f32> = rgb_image(colors.clone(), primaries).into();
+
+			assert!((lab.data[0] - 100.0).abs() < 1e-3 && (oklab.data[0] - 1.0).abs() < 1e-3);
+			for pixel in lab.data[..6].chunks(3).chain(oklab.data[..6].chunks(3)) {
+				assert!(pixel[1].abs() < 1e-3 && pixel[2].abs() < 1e-3, "{:?} in {:?}", pixel, primaries);
+			}
+
+			let round_trips: [Image<Rgb, f32>; 3] = [
+				Image::<Lab, f32>::from(rgb_image(colors.clone(), primaries)).into(),
+				Image::<Lch, f32>::from(rgb_image(colors.clone(), primaries)).into(),
+				Image::<Oklab, f32>::from(rgb_image(colors.clone(), primaries)).into()
+			];
+			for rgb in round_trips.iter() {
+				assert_close(&rgb.data[..], &colors[..], 1e-4);
+			}
+		}
+	}
+
+	// Two primaries the same can't be gotten back to from XYZ
+	#[test]
+	#[should_panic(expected = "invertible")]
+	fn degenerate_primaries() {
+		let primaries = Primaries { green: Primaries::SRGB.red, ..Primaries::SRGB };
+		let mut xyz: Image<Xyz, f32> = rgb_image(vec![0.5, 0.5, 0.5], Primaries::SRGB).into();
+		xyz.meta.primaries = primaries;
+		let _: Image<Rgb, f32> = xyz.into();
+	}
+
+	// Simple colors. White, gray, and maxed Red, Green, and Blue
+	#[test]
+	fn rgb_to_hsl_simple() {
+		let (_h, s, l) = Processor::pixel_rgb_to_hsl(1.0, 1.0, 1.0);
+		assert_eq!((s, l), (0.0, 1.0));
+
+		let (_h, s, l) = Processor::pixel_rgb_to_hsl(0.5, 0.5, 0.5);
+		assert_eq!((s, l), (0.0, 0.5));
+
+		assert_eq!(Processor::pixel_rgb_to_hsl(1.0, 0.0, 0.0), (0.0, 1.0, 0.5));
+		assert_eq!(Processor::pixel_rgb_to_hsl(0.0, 1.0, 0.0), (120.0, 1.0, 0.5));
+		assert_eq!(Processor::pixel_rgb_to_hsl(0.0, 0.0, 1.0), (240.0, 1.0, 0.5));
+	}
+
+	// Values from the table on Wikipedia's HSL and HSV page
+	#[test]
+	fn rgb_to_hsl() {
+		let tolerance = 3.9e-3; // 1 step in 8bit color
+
+		assert_close(Processor::pixel_rgb_to_hsl(0.750, 0.750, 0.0), (60.0, 1.0, 0.375), tolerance);
+		assert_close(Processor::pixel_rgb_to_hsl(0.628, 0.643, 0.142), (61.8, 0.638, 0.393), 0.1);
+		assert_close(Processor::pixel_rgb_to_hsl(0.255, 0.104, 0.918), (251.1, 0.832, 0.511), 0.1);
+		assert_close(Processor::pixel_rgb_to_hsl(0.495, 0.493, 0.721), (240.5, 0.290, 0.607), 0.1);
+	}
+
+	#[test]
+	fn hsl_round_trip() {
+		use crate::image::HslComponent;
+
+		let colors = [(1.0, 1.0, 1.0), (0.0, 0.0, 0.0), (0.438, 0.875, 0.875), (0.25, 0.125, 0.125), (0.9, 0.1, 0.6)];
+
+		for &(r, g, b) in colors.iter() {
+			let (h, s, l) = Processor::pixel_rgb_to_hsl(r, g, b);
+			assert_close(Processor::pixel_hsl_to_rgb(h, s, l), (r, g, b), 1e-5);
+		}
+
+		// Darkish cyan, through the image and its components
+		let hsl: Image<Hsl, f32> = rgb_image(vec![0.438, 0.875, 0.875], Primaries::SRGB).into();
+		assert_close(hsl.component(0, 0, HslComponent::Hue), 180.0, 1e-3);
+		assert_close(hsl.component(0, 0, HslComponent::Lightness), 0.6565, 1e-3);
+	}
+
+	#[test]
+	fn ycbcr() {
+		let colors = vec![1.0, 0.0, 0.0, 1.0, 1.0, 1.0, 0.25, 0.125, 0.125, 0.1, 0.5, 0.9];
+
+		// Red, from each standard's luma coefficients, and white
+		let published = [
+			(YCbCrMatrix::Bt601, (0.299, -0.168736, 0.5)),
+			(YCbCrMatrix::Bt709, (0.2126, -0.114572, 0.5)),
+			(YCbCrMatrix::Bt2020, (0.2627, -0.139630, 0.5))
+		];
+
+		for &(matrix, red) in published.iter() {
+			let ycbcr = Processor::rgb_to_ycbcr(rgb_image(colors.clone(), Primaries::SRGB), matrix);
+			assert_close((ycbcr.data[0], ycbcr.data[1], ycbcr.data[2]), red, 1e-5);
+			assert_close((ycbcr.data[3], ycbcr.data[4], ycbcr.data[5]), (1.0, 0.0, 0.0), 1e-5);
+
+			let rgb: Image<Rgb, f32> = ycbcr.into();
+			assert_close(&rgb.data[..], &colors[..], 1e-5);
+		}
+	}
 }
\ No newline at end of file
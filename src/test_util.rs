@@ -0,0 +1,68 @@
+// Fixtures and assertions shared by the tests in every module
+
+use crate::color::Matrix3;
+use crate::color_management::Primaries;
+use crate::image::{CFA, Image, Metadata, Rgb};
+use std::fmt::Debug;
+
+// Anything that's a few floats, to be compared within a tolerance
+pub(crate) trait Floats: Debug {
+	fn floats(&self) -> Vec<f64>;
+}
+
+impl Floats for f32 {
+	fn floats(&self) -> Vec<f64> {
+		vec![*self as f64]
+	}
+}
+
+impl Floats for f64 {
+	fn floats(&self) -> Vec<f64> {
+		vec![*self]
+	}
+}
+
+impl Floats for (f64, f64) {
+	fn floats(&self) -> Vec<f64> {
+		vec![self.0, self.1]
+	}
+}
+
+impl Floats for (f32, f32, f32) {
+	fn floats(&self) -> Vec<f64> {
+		vec![self.0 as f64, self.1 as f64, self.2 as f64]
+	}
+}
+
+impl Floats for [f64; 3] {
+	fn floats(&self) -> Vec<f64> {
+		self.to_vec()
+	}
+}
+
+impl Floats for Matrix3 {
+	fn floats(&self) -> Vec<f64> {
+		self.iter().flat_map(|row| row.iter().copied()).collect()
+	}
+}
+
+impl Floats for &[f32] {
+	fn floats(&self) -> Vec<f64> {
+		self.iter().map(|&value| value as f64).collect()
+	}
+}
+
+pub(crate) fn assert_close<T: Floats>(a: T, b: T, tolerance: f64) {
+	let (left, right) = (a.floats(), b.floats());
+
+	if left.len() != right.len() || left.iter().zip(right.iter()).any(|(l, r)| (l - r).abs() > tolerance) {
+		panic!("assertion failed: `(left ~ right)`\n\tLeft: `{:?}`\n\tRight: `{:?}`\n\tTolerance: {}", a, b, tolerance);
+	}
+}
+
+// An RGB image in `primaries` a row of pixels long, three components each
+pub(crate) fn rgb_image(data: Vec<f32>, primaries: Primaries) -> Image<Rgb, f32> {
+	let mut meta = Metadata::test(data.len() as u32 / 3, 1, CFA::RGGB);
+	meta.primaries = primaries;
+	Image { kind: Rgb, data, meta }
+}